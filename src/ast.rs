@@ -1,10 +1,15 @@
 use parser::Op;
+use parser::ParseError;
 use parser::TokStruct;
 
+// Marker traits for the kinds of AST node, implemented through their derive macros.
+#[allow(unused)]
 pub trait NodeT {}
 
+#[allow(unused)]
 pub trait EncapsulatingNode: NodeT {}
 
+#[allow(unused)]
 pub trait TerminalNode: NodeT {}
 
 #[derive(Debug, Clone, Default)]
@@ -18,14 +23,27 @@ pub struct Program {
 #[allow(unused)]
 #[repr(C)]
 pub struct ArgList {    
-    pub argv: Vec<Expr>
+    pub argv: Vec<Expr>,
+    // Arguments given by name, as in 'f(x, tol = 1e-6)'.
+    pub kwargs: Vec<(TokStruct, Expr)>
+}
+
+// A parameter of a function, with the value it takes when it is not given one.
+#[derive(Debug, Clone, EncapsulatingNode, Default)]
+#[allow(unused)]
+#[repr(C)]
+pub struct Param {
+    pub name: TokStruct,
+    pub default: Option<Expr>
 }
 
 #[derive(Debug, Clone, EncapsulatingNode, Default)]
 #[allow(unused)]
 #[repr(C)]
 pub struct ArgDeclList {
-    pub argv: Vec<TokStruct>
+    pub argv: Vec<Param>,
+    // The '*args' parameter, which collects any extra arguments into a tuple.
+    pub rest: Option<TokStruct>
 }
 
 #[derive(Debug, Clone, EncapsulatingNode, Default)]
@@ -36,29 +54,53 @@ pub struct FnCall {
     pub args: ArgList
 }
 
+// A list literal such as '[1, 2, 3]'. The opening bracket is kept for error positions.
+#[derive(Debug, Clone, EncapsulatingNode, Default)]
+#[allow(unused)]
+#[repr(C)]
+pub struct ArrayLiteral {
+    pub start: TokStruct,
+    pub items: Vec<Expr>
+}
+
+// A tuple such as '(1, 2)' or 'a, b'. The first token is kept for error positions.
+#[derive(Debug, Clone, EncapsulatingNode, Default)]
+#[allow(unused)]
+#[repr(C)]
+pub struct TupleLiteral {
+    pub start: TokStruct,
+    pub items: Vec<Expr>
+}
+
+// A map literal such as '{ "a": 1, "b": 2 }', holding each key and value in order.
+#[derive(Debug, Clone, EncapsulatingNode, Default)]
+#[allow(unused)]
+#[repr(C)]
+pub struct MapLiteral {
+    pub start: TokStruct,
+    pub items: Vec<(Expr, Expr)>
+}
+
 #[derive(Debug, Clone, NodeT)]
 #[allow(unused)]
 #[repr(C)]
 pub enum Factor {
     Int(TokStruct),
     Float(TokStruct),
+    Imag(TokStruct),
     String(TokStruct),
     Symbol(TokStruct),
     Bool(TokStruct),
     FnCall(FnCall),
+    // An anonymous function, as in '|x| x ** 2'. Its name is always 'lambda'.
+    Lambda(Function),
+    Array(ArrayLiteral),
+    Map(MapLiteral),
+    Tuple(TupleLiteral),
     Expr(Expr),
     None
 }
 
-impl Factor {
-    pub fn as_fn_call(&mut self) -> Option<&mut FnCall> {
-        match self {
-            Factor::FnCall(f) => Some(f),
-            _ => None
-        }
-    }
-}
-
 #[derive(Debug, Clone, NodeT)]
 #[allow(unused)]
 #[repr(C)]
@@ -72,6 +114,37 @@ pub enum Expr {
         right: Box<Expr>,
         op: Op
     },
+    // 'if_true if cond else if_false', which picks one of two values. Only the one
+    //   that is picked gets evaluated.
+    Conditional {
+        cond: Box<Expr>,
+        if_true: Box<Expr>,
+        if_false: Box<Expr>
+    },
+    // A chain of two or more comparisons, as in 'a < b <= c'. It means 'a < b && b <= c',
+    //   except that 'b' is only evaluated once.
+    Compare {
+        first: Box<Expr>,
+        rest: Vec<(Op, Expr)>
+    },
+    // 'target(args)', a call on anything that gives a function, as in 'adder(1)(2)'.
+    //   A call on a plain name is a 'Factor::FnCall' instead.
+    Call {
+        target: Box<Expr>,
+        args: ArgList
+    },
+    // 'target[index]'.
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>
+    },
+    // 'target[start:end:step]', where any of the three can be left out.
+    Slice {
+        target: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        step: Option<Box<Expr>>
+    },
     Factor(Box<Factor>)
 }
 
@@ -92,16 +165,52 @@ pub enum Statement {
         left: Expr,
         right: Expr
     },
+    // 'left op= right', as in 'x += 1'. The op is the plain operator, like '+'.
+    CompoundAssign {
+        left: Expr,
+        op: Op,
+        right: Expr
+    },
     Return {
         val: Expr
     },
+    // 'array a = value', which declares 'a' in the current scope.
+    ArrayDecl {
+        name: TokStruct,
+        value: Option<Expr>
+    },
     Branch {
         if_block: CondBlock,
         alt_blocks: Vec<CondBlock>,
         else_block: Option<Box<Statement>>
     },
+    // 'state x = value', which declares a global 'x' that is saved between REPL sessions.
+    State {
+        name: TokStruct,
+        value: Option<Expr>
+    },
+    // 'while cond body', which runs the body for as long as the condition holds.
+    While(CondBlock),
+    // 'for var in iter body', which runs the body once for each item of an array, tuple
+    //   or string, or each key of a map. The target can be several names, as in
+    //   'for k, v in m', which unpack each item, or each key and value of a map.
+    For {
+        target: Expr,
+        iter: Expr,
+        body: Box<Statement>
+    },
+    // The keyword tokens are kept so a stray 'break' can be pointed at.
+    Break(TokStruct),
+    Continue(TokStruct),
+    // 'write a, b', which prints the values on one line.
+    Write(Vec<Expr>),
+    // 'read x', which asks for a value and stores it in 'x'.
+    Read(TokStruct),
     FnDecl(Function),
-    Scope(Scope)
+    Scope(Scope),
+
+    // Placeholder for a statement that failed to parse.
+    Error(ParseError)
 }
 
 #[derive(Debug, Clone, EncapsulatingNode, Default)]
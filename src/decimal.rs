@@ -0,0 +1,232 @@
+use bigdecimal::{BigDecimal, Context, RoundingMode};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{pow, FromPrimitive, Signed, ToPrimitive, Zero};
+
+use std::cell::Cell;
+use std::num::NonZeroU64;
+
+// Extra digits carried through intermediate steps, so the final rounding
+//   to the requested precision is not thrown off by earlier ones.
+const GUARD_DIGITS: u64 = 10;
+
+// The decimal precision and rounding mode, set with the ':precision' and ':rounding'
+//   REPL commands. Arithmetic reads them here, since the operator traits cannot be
+//   passed settings. With no precision set, the calculator sticks to floats.
+thread_local! {
+    static SETTINGS: Cell<(Option<u64>, RoundingMode)> = const { Cell::new((None, RoundingMode::HalfEven)) };
+}
+
+// The context decimal arithmetic should use, or None if decimals are turned off.
+pub fn context() -> Option<Context> {
+    let (digits, rounding) = SETTINGS.with(|s| s.get());
+
+    digits
+        .and_then(NonZeroU64::new)
+        .map(|d| Context::new(d, rounding))
+}
+
+pub fn precision() -> Option<u64> {
+    SETTINGS.with(|s| s.get().0)
+}
+
+// Sets the number of significant digits. None goes back to floats.
+pub fn set_precision(digits: Option<u64>) {
+    SETTINGS.with(|s| {
+        let (_, rounding) = s.get();
+        s.set((digits, rounding));
+    });
+}
+
+pub fn rounding() -> RoundingMode {
+    SETTINGS.with(|s| s.get().1)
+}
+
+pub fn set_rounding(mode: RoundingMode) {
+    SETTINGS.with(|s| {
+        let (digits, _) = s.get();
+        s.set((digits, mode));
+    });
+}
+
+// The same context with some guard digits added.
+fn widen(ctx: &Context) -> Context {
+    ctx.with_precision(NonZeroU64::new(ctx.precision().get() + GUARD_DIGITS).unwrap())
+}
+
+fn ten_pow(n: u64) -> BigInt {
+    pow(BigInt::from(10), n as usize)
+}
+
+// The exact value of a decimal as a fraction.
+pub fn to_rational(x: &BigDecimal) -> BigRational {
+    let (digits, scale) = x.as_bigint_and_exponent();
+
+    if scale >= 0 {
+        BigRational::new(digits, ten_pow(scale as u64))
+    } else {
+        BigRational::from_integer(digits * ten_pow(scale.unsigned_abs()))
+    }
+}
+
+// Rounds a fraction to a decimal, following the rounding mode of the context.
+//
+// The division is carried out to one more digit than the precision. If it does not
+//   come out exact, a 1 is tacked on the end, so something just above a halfway
+//   point is never mistaken for an exact halfway point when rounding.
+pub fn from_rational(x: &BigRational, ctx: &Context) -> BigDecimal {
+    let (n, d) = (x.numer(), x.denom());
+
+    if n.is_zero() {
+        return BigDecimal::zero();
+    }
+
+    let digits = |v: &BigInt| v.abs().to_string().len() as i64;
+    let scale = ctx.precision().get() as i64 + 2 - (digits(n) - digits(d));
+
+    let (q, r) = if scale >= 0 {
+        (n * ten_pow(scale as u64)).div_rem(d)
+    } else {
+        n.div_rem(&(d * ten_pow(scale.unsigned_abs())))
+    };
+
+    let (q, scale) = if r.is_zero() {
+        (q, scale)
+    } else {
+        (q * 10 + n.signum(), scale + 1)
+    };
+
+    ctx.round_decimal(BigDecimal::new(q, scale))
+}
+
+pub fn div(x: &BigDecimal, y: &BigDecimal, ctx: &Context) -> BigDecimal {
+    from_rational(&(to_rational(x) / to_rational(y)), ctx)
+}
+
+// Square roots of negative numbers give None.
+pub fn sqrt(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    x.sqrt_with_context(ctx)
+}
+
+pub fn exp(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    ctx.round_decimal(x.exp_with_context(&widen(ctx)))
+}
+
+// The natural logarithm. Numbers that are not positive give None.
+//
+// The number is split into m * 10^k with m between 0.1 and 1, so that
+//   ln(x) = ln(m) + k * ln(10), and both logarithms are found with ln_newton().
+pub fn ln(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    if !x.is_positive() {
+        return None;
+    }
+
+    let wide = widen(ctx);
+    let (digits, scale) = x.as_bigint_and_exponent();
+    let n = digits.to_string().len() as i64;
+
+    let mut y = ln_newton(&BigDecimal::new(digits, n), &wide);
+
+    if n != scale {
+        y += ln_newton(&BigDecimal::from(10), &wide) * BigDecimal::from(n - scale);
+    }
+
+    Some(ctx.round_decimal(y))
+}
+
+// Refines the float logarithm of a number that fits in a float, using the iteration
+//   y = y + 2 * (x - e^y) / (x + e^y). Each step triples the number of correct digits.
+fn ln_newton(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let mut y = BigDecimal::from_f64(x.to_f64().unwrap_or(1.).ln()).unwrap_or_default();
+    let mut correct = 14;
+
+    while correct < ctx.precision().get() {
+        let e = y.exp_with_context(ctx);
+        let step = div(&((x - &e) * BigDecimal::from(2)), &(x + &e), ctx);
+        y = ctx.round_decimal(y + step);
+        correct *= 3;
+    }
+
+    y
+}
+
+pub fn log10(x: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wide = widen(ctx);
+
+    ln(x, &wide).map(|y| div(&y, &ln_newton(&BigDecimal::from(10), &wide), ctx))
+}
+
+// A positive number to any power, as e^(y * ln(x)).
+pub fn powf(x: &BigDecimal, y: &BigDecimal, ctx: &Context) -> Option<BigDecimal> {
+    let wide = widen(ctx);
+
+    ln(x, &wide).map(|l| exp(&(l * y), ctx))
+}
+
+// Pi, from Machin's formula pi = 16 * atan(1/5) - 4 * atan(1/239).
+pub fn pi(ctx: &Context) -> BigDecimal {
+    let digits = ctx.precision().get() + GUARD_DIGITS;
+    let unity = ten_pow(digits);
+    let pi = (atan_inv(5, &unity) * 4 - atan_inv(239, &unity)) * 4;
+
+    ctx.round_decimal(BigDecimal::new(pi, digits as i64))
+}
+
+// atan(1/x) as a fixed point number, scaled by unity. It is summed from the series
+//   1/x - 1/(3x^3) + 1/(5x^5) - ... until the terms are too small to matter.
+fn atan_inv(x: u32, unity: &BigInt) -> BigInt {
+    let x2 = BigInt::from(x * x);
+    let mut power = unity / BigInt::from(x);
+    let mut sum = power.clone();
+    let mut n = 1u32;
+
+    loop {
+        power /= &x2;
+        n += 2;
+
+        let term = &power / BigInt::from(n);
+        if term.is_zero() {
+            break;
+        }
+
+        if n % 4 == 3 {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+    }
+
+    sum
+}
+
+pub fn e(ctx: &Context) -> BigDecimal {
+    exp(&BigDecimal::from(1), ctx)
+}
+
+// The names the ':rounding' command accepts.
+pub fn rounding_from_name(name: &str) -> Option<RoundingMode> {
+    match name {
+        "up" => Some(RoundingMode::Up),
+        "down" => Some(RoundingMode::Down),
+        "ceiling" => Some(RoundingMode::Ceiling),
+        "floor" => Some(RoundingMode::Floor),
+        "half-up" => Some(RoundingMode::HalfUp),
+        "half-down" => Some(RoundingMode::HalfDown),
+        "half-even" => Some(RoundingMode::HalfEven),
+        _ => None,
+    }
+}
+
+pub fn rounding_name(mode: RoundingMode) -> &'static str {
+    match mode {
+        RoundingMode::Up => "up",
+        RoundingMode::Down => "down",
+        RoundingMode::Ceiling => "ceiling",
+        RoundingMode::Floor => "floor",
+        RoundingMode::HalfUp => "half-up",
+        RoundingMode::HalfDown => "half-down",
+        RoundingMode::HalfEven => "half-even",
+    }
+}
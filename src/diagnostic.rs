@@ -0,0 +1,330 @@
+use interpreter::RuntimeError;
+use parser::{ParseError, ParseErrorKind, Token};
+
+use termion::color;
+use termion::style;
+
+use std::ops::Range;
+use std::rc::Rc;
+
+// A renderable error report. Both parse and runtime errors are converted into
+//   one of these, which can then be printed with a snippet of the offending source.
+//
+// The rendered form looks like this -
+//
+// error: expected ')', found end of statement
+//  --> 1:26
+//   |
+// 1 | fn main() { return (1 + 2; }
+//   |                          ^ expected ')'
+//   = help: try inserting ')' here
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Range<usize>,
+    pub label: Option<String>,
+    pub help: Option<String>,
+    // The source the span is in, when it is not the source being rendered against,
+    //   such as the line a function was declared on.
+    pub source: Option<Rc<str>>,
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Diagnostic {
+        let expected = e.expected
+            .iter()
+            .map(|t| t.kind_name())
+            .collect::<Vec<String>>()
+            .join(" or ");
+
+        let (label, help) = match e.kind {
+            ParseErrorKind::InvalidNumber => (
+                Some("not a number".to_owned()),
+                Some("a number can contain at most one '.', and a '0x', '0o' or '0b' prefix must be followed by digits".to_owned()),
+            ),
+            ParseErrorKind::UnknownSymbol => (
+                Some("unknown symbol".to_owned()),
+                Some("this character has no meaning in RCScript".to_owned()),
+            ),
+            ParseErrorKind::UnterminatedString => (
+                Some("string starts here".to_owned()),
+                Some("add a closing quote before the end of the line".to_owned()),
+            ),
+            ParseErrorKind::InvalidEscape => (
+                Some("unknown escape".to_owned()),
+                Some("the escapes are \\n, \\t, \\r, \\0, \\\\, \\', \\\" and \\u{...}".to_owned()),
+            ),
+            ParseErrorKind::UnterminatedComment => (
+                Some("comment starts here".to_owned()),
+                Some("add '*/' to close the comment".to_owned()),
+            ),
+            ParseErrorKind::TooDeep => (
+                Some("nested too deeply".to_owned()),
+                Some("work out the inner parts first and keep them in variables".to_owned()),
+            ),
+            ParseErrorKind::PositionalAfterKeyword => (
+                Some("positional argument".to_owned()),
+                Some("move it before the keyword arguments".to_owned()),
+            ),
+            ParseErrorKind::OutsideLoop => (
+                Some("not inside a loop".to_owned()),
+                Some("'break' and 'continue' only work in the body of a 'for' or 'while' loop".to_owned()),
+            ),
+            ParseErrorKind::UnexpectedToken => match e.expected.as_slice() {
+                [] => (Some("unexpected token".to_owned()), None),
+                // A single missing delimiter is usually a typo that can be fixed in place.
+                [Token::Operator(_)] => (
+                    Some(format!("expected {}", expected)),
+                    Some(format!("try inserting {} here", expected)),
+                ),
+                _ => (Some(format!("expected {}", expected)), None),
+            },
+        };
+
+        Diagnostic {
+            message: e.message(),
+            span: e.span.clone(),
+            label,
+            help,
+            source: None,
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(e: &RuntimeError) -> Diagnostic {
+        Diagnostic {
+            message: e.msg.clone(),
+            span: e.pos..e.pos + 1,
+            label: None,
+            help: None,
+            source: e.source.clone(),
+        }
+    }
+}
+
+impl Diagnostic {
+    // Renders the diagnostic as plain text, one entry per output line.
+    pub fn render(&self, source: &str) -> Vec<String> {
+        self.render_styled(source, false)
+    }
+
+    // Renders the diagnostic with terminal colours for use in a raw mode terminal.
+    pub fn render_coloured(&self, source: &str) -> Vec<String> {
+        self.render_styled(source, true)
+    }
+
+    fn render_styled(&self, source: &str, colour: bool) -> Vec<String> {
+        let paint = |text: String, c: &dyn color::Color| -> String {
+            if colour {
+                format!("{}{}{}{}", style::Bold, color::Fg(c), text, style::Reset)
+            } else {
+                text
+            }
+        };
+
+        let source = match self.source {
+            Some(ref s) => s,
+            None => source,
+        };
+
+        let mut out = Vec::new();
+
+        out.push(format!(
+            "{}{}",
+            paint("error".to_owned(), &color::Red),
+            paint(format!(": {}", self.message), &color::Reset)
+        ));
+
+        let (line_no, col, line, line_start) = locate(source, self.span.start);
+
+        // Line numbers are shown 1 indexed, and the gutter is as wide as the line number.
+        let gutter = " ".repeat(line_no.to_string().len());
+
+        out.push(format!("{}{} {}:{}", gutter, paint("-->".to_owned(), &color::Blue), line_no, col + 1));
+        out.push(format!("{} {}", gutter, paint("|".to_owned(), &color::Blue)));
+        out.push(format!(
+            "{} {} {}",
+            paint(line_no.to_string(), &color::Blue),
+            paint("|".to_owned(), &color::Blue),
+            line
+        ));
+
+        // The underline stops at the end of the line, even if the span does not.
+        let end = ::std::cmp::min(self.span.end.saturating_sub(line_start), line.len());
+        let width = ::std::cmp::max(
+            1,
+            line.char_indices().filter(|&(i, _)| i < end).count().saturating_sub(col),
+        );
+        let marker = format!(
+            "{}{}",
+            "^".repeat(width),
+            match self.label {
+                Some(ref l) => format!(" {}", l),
+                None => String::new(),
+            }
+        );
+
+        out.push(format!(
+            "{} {} {}{}",
+            gutter,
+            paint("|".to_owned(), &color::Blue),
+            " ".repeat(col),
+            paint(marker, &color::Red)
+        ));
+
+        if let Some(ref h) = self.help {
+            out.push(format!("{} {} {}", gutter, paint("=".to_owned(), &color::Blue), paint(format!("help: {}", h), &color::Cyan)));
+        }
+
+        out
+    }
+}
+
+// Finds the 1 indexed line number, 0 indexed column and text of the line containing a byte
+//   offset, along with the offset the line starts at. The column counts characters, so the
+//   caret lines up under text that is not ASCII.
+fn locate(source: &str, pos: usize) -> (usize, usize, &str, usize) {
+    let mut pos = ::std::cmp::min(pos, source.len());
+
+    // The end of input is shown at the end of the last line rather than on an empty line after it.
+    if pos == source.len() && source.ends_with('\n') {
+        pos -= 1;
+    }
+
+    // An offset inside a character points at the whole character.
+    while !source.is_char_boundary(pos) {
+        pos -= 1;
+    }
+
+    let start = source[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = source[pos..]
+        .find('\n')
+        .map(|i| i + pos)
+        .unwrap_or(source.len());
+    let line_no = source[..start].matches('\n').count() + 1;
+
+    (
+        line_no,
+        source[start..pos].chars().count(),
+        source[start..end].trim_end_matches('\r'),
+        start,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::Parser;
+
+    fn diagnostic(start: usize, end: usize, label: &str) -> Diagnostic {
+        Diagnostic {
+            message: "oops".to_owned(),
+            span: start..end,
+            label: Some(label.to_owned()),
+            help: None,
+            source: None,
+        }
+    }
+
+    // Renders the first error in parsing some source.
+    fn rendered(source: &str) -> Vec<String> {
+        let mut parser = Parser::new();
+        parser.input(source.to_owned());
+
+        match parser.parse_repl_line() {
+            Err(errors) => Diagnostic::from(&errors[0]).render(source),
+            Ok(_) => panic!("{} parsed", source),
+        }
+    }
+
+    #[test]
+    fn errors_show_the_line_with_a_caret() {
+        assert_eq!(
+            rendered("x = (1 + 2; y"),
+            vec![
+                "error: expected ')', found end of statement",
+                " --> 1:11",
+                "  |",
+                "1 | x = (1 + 2; y",
+                "  |           ^ expected ')'",
+                "  = help: try inserting ')' here",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_end_of_input_is_just_past_the_last_character() {
+        assert_eq!(
+            rendered("(1 + 2"),
+            vec![
+                "error: expected ')', found end of input",
+                " --> 1:7",
+                "  |",
+                "1 | (1 + 2",
+                "  |       ^ expected ')'",
+                "  = help: try inserting ')' here",
+            ]
+        );
+
+        // A trailing newline does not move it onto an empty line.
+        let out = diagnostic(8, 9, "here").render("x = 1 +\n");
+        assert_eq!(out[1], " --> 1:8");
+        assert_eq!(out[3], "1 | x = 1 +");
+        assert_eq!(out[4], "  |        ^ here");
+    }
+
+    #[test]
+    fn errors_on_later_lines_show_that_line() {
+        assert_eq!(
+            rendered("fn f() {\n  return (2 +\n}"),
+            vec![
+                "error: expected number or identifier or '(', found end of statement",
+                " --> 2:14",
+                "  |",
+                "2 |   return (2 +",
+                "  |              ^ expected number or identifier or '('",
+            ]
+        );
+
+        // The gutter widens to fit the line number.
+        let source = format!("{}x = 1 +* 2\r\ny = 3", "\n".repeat(9));
+        let out = diagnostic(17, 18, "here").render(&source);
+        assert_eq!(out[1], "  --> 10:9");
+        assert_eq!(out[2], "   |");
+        assert_eq!(out[3], "10 | x = 1 +* 2");
+        assert_eq!(out[4], "   |         ^ here");
+
+        // A span running past the end of its line is cut off there.
+        let out = diagnostic(4, 20, "here").render("x = 1 +\ny = 2");
+        assert_eq!(out[4], "  |     ^^^ here");
+    }
+
+    #[test]
+    fn carets_count_characters() {
+        let source = "\"éé\" + x";
+        let out = diagnostic(9, 10, "here").render(source);
+
+        assert_eq!(out[1], " --> 1:8");
+        assert_eq!(out[4], "  |        ^ here");
+    }
+
+    #[test]
+    fn offsets_inside_a_character_do_not_panic() {
+        let out = diagnostic(2, 4, "here").render("\"é\"");
+
+        assert_eq!(out[4], "  |  ^^ here");
+    }
+
+    #[test]
+    fn spans_in_other_source_are_shown_there() {
+        let mut d = diagnostic(17, 18, "here");
+        d.source = Some(Rc::from("fn f(x) { return 1/x; }"));
+
+        let out = d.render("f(0)");
+
+        assert_eq!(out[3], "1 | fn f(x) { return 1/x; }");
+        assert_eq!(out[4], "  |                  ^ here");
+    }
+}
@@ -0,0 +1,1495 @@
+use ast::{ArgList, Expr, Factor, FnCall, Function, Param, Program, Scope, Statement};
+use builtins;
+use io::Console;
+use parser::{Op, Parser, SymTable, TokStruct};
+use value::{Value, ValueResult, MAX_LENGTH};
+use word;
+
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive};
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// An error raised while evaluating a program. The position is a byte offset
+//   into the source, taken from the nearest token of the offending node.
+//   An error inside a function written on an earlier REPL line carries
+//   that line along, since the position is in it rather than the current one.
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub msg: String,
+    pub pos: usize,
+    pub source: Option<Rc<str>>,
+}
+
+impl RuntimeError {
+    pub fn new(msg: String, pos: usize) -> RuntimeError {
+        RuntimeError { msg, pos, source: None }
+    }
+
+    // Marks the error as being in the given source, unless an inner call already has.
+    fn in_source(mut self, source: &Rc<str>) -> RuntimeError {
+        if self.source.is_none() {
+            self.source = Some(source.clone());
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runtime error at position {}: {}", self.pos, self.msg)
+    }
+}
+
+pub type EvalResult<T> = Result<T, RuntimeError>;
+
+// The arguments of a call, given in order and by name.
+type Args = (Vec<Value>, Vec<(String, Value)>);
+
+// What a statement asks the enclosing block to do once it has run.
+enum Flow {
+    Next,
+    Return(Value),
+    // Leave or restart the innermost loop. The parser only allows these inside
+    //   a loop, so they never make it out of one.
+    Break,
+    Continue,
+}
+
+// A single lexical scope. Variables and functions live in separate namespaces,
+//   so a variable can share its name with a function.
+//
+// Scopes are shared, since a closure keeps the scopes it was created in alive
+//   after the block or call that made them has finished.
+#[derive(Default)]
+pub struct Frame {
+    vars: SymTable<Value>,
+    fns: HashMap<String, Rc<Closure>>,
+}
+
+type Scope_ = Rc<RefCell<Frame>>;
+
+fn new_scope() -> Scope_ {
+    Rc::new(RefCell::new(Frame::default()))
+}
+
+// A function value: a named function or a lambda, along with the scopes it was
+//   created in. Those scopes are captured by reference, so the function sees
+//   later changes to their variables, and assigning to one changes it for everyone.
+//   The global scope is never captured since it is always visible.
+pub struct Closure {
+    pub func: Rc<Function>,
+    env: Vec<Scope_>,
+    // The source the function was written in, which its token positions refer to.
+    source: Rc<str>,
+    // Whether the function was declared with a name it can call itself by.
+    //   Lambdas are all called 'lambda', which is not a name they can be called by.
+    named: bool,
+}
+
+impl Closure {
+    pub fn name(&self) -> String {
+        self.func.name.get_val().unwrap_as_string().unwrap_or_default()
+    }
+}
+
+// The captured scopes can contain the closure itself, so they are left out.
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Closure({})", self.name())
+    }
+}
+
+// How deeply function calls can nest. Runaway recursion is stopped with an error
+//   here, well before it can overflow the real stack.
+const MAX_CALL_DEPTH: usize = 1000;
+
+// How deeply expressions and statements can nest while being run, counting the ones in
+//   every function call under way. The parser limits how deep a single expression can be,
+//   so this only comes into play for deep expressions in recursive functions.
+const MAX_EVAL_DEPTH: usize = 4000;
+
+// The stack the parser and interpreter need to reach their limits with room to spare.
+//   An optimised build fits in the usual 8 MiB, but an unoptimised one uses several
+//   times as much stack for each level, so it gets more.
+pub const STACK_SIZE: usize = if cfg!(debug_assertions) { 128 } else { 8 } * 1024 * 1024;
+
+// The tree-walking interpreter.
+//
+// The scope stack always has the global scope at the bottom. Entering a block
+//   pushes a new scope, and a function call temporarily sets aside every scope
+//   above the globals, replacing them with the scopes the function was created in,
+//   so a function body only sees the globals, its own locals and what it captured.
+pub struct Interpreter {
+    scopes: Vec<Scope_>,
+    // Where 'write' prints to and 'read' reads from.
+    console: Console,
+    // The names of the global variables declared with 'state', in the order
+    //   they were declared.
+    state: Vec<String>,
+    // How many function calls are currently running.
+    depth: usize,
+    // How many expressions and statements are currently being run.
+    nesting: usize,
+    // The source being run, which functions declared in it keep for their errors.
+    source: Rc<str>,
+}
+
+impl Interpreter {
+    pub fn new(console: Console) -> Interpreter {
+        Interpreter {
+            scopes: vec![new_scope()],
+            console,
+            state: Vec::new(),
+            depth: 0,
+            nesting: 0,
+            source: Rc::from(""),
+        }
+    }
+
+    // Sets the source of the statements run next, as with Parser::input.
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Rc::from(source);
+    }
+
+    pub fn console(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    // Registers every function in the program in the global scope,
+    //   then calls 'main' if the program defines one.
+    pub fn run(&mut self, program: &Program) -> EvalResult<Option<Value>> {
+        for f in &program.content {
+            self.define_fn(f);
+        }
+
+        match self.lookup_closure("main") {
+            Some(f) => {
+                let pos = f.func.name.get_pos();
+                self.call_fn(f, Vec::new(), Vec::new(), pos).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Runs the statements of one REPL line in the global scope, so variables and
+    //   functions persist between lines. Returns the value of the last statement
+    //   if it was an expression.
+    pub fn exec_line(&mut self, stmts: &[Statement]) -> EvalResult<Option<Value>> {
+        let mut last = None;
+
+        for s in stmts {
+            last = match s {
+                Statement::Expr(e) => Some(self.eval(e)?),
+                _ => match self.exec(s)? {
+                    Flow::Return(v) => return Ok(Some(v)),
+                    _ => None,
+                },
+            };
+        }
+
+        // Calls to functions that do not return anything have no value to show.
+        Ok(match last {
+            Some(Value::None) => None,
+            l => l,
+        })
+    }
+
+    // The state variables as RCScript, one 'state' declaration per line, so loading
+    //   them again is just a matter of running the text. Variables that are gone
+    //   or hold nothing are left out.
+    pub fn save_state(&self) -> String {
+        let mut out = String::new();
+
+        for name in &self.state {
+            // Values that can't be written as literals, like functions, which are code
+            //   rather than data, are left out so the file always loads.
+            match self.scopes[0].borrow().vars.get(name) {
+                Some(v) if v.is_writable() => out.push_str(&format!("state {} = {};\n", name, v.repr())),
+                _ => {}
+            }
+        }
+
+        out
+    }
+
+    fn define_fn(&mut self, f: &Function) {
+        let name = f.name.get_val().unwrap_as_string().unwrap_or_default();
+        let closure = self.closure(f, true);
+
+        self.scopes.last().unwrap().borrow_mut().fns.insert(name, closure);
+    }
+
+    // Makes a function value for a function declared or written here.
+    fn closure(&self, f: &Function, named: bool) -> Rc<Closure> {
+        Rc::new(Closure {
+            func: Rc::new(f.clone()),
+            env: self.scopes[1..].to_vec(),
+            source: self.source.clone(),
+            named,
+        })
+    }
+
+    fn lookup_closure(&self, name: &str) -> Option<Rc<Closure>> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|s| s.borrow().fns.get(name).cloned())
+            .next()
+    }
+
+    // Constants like pi are looked up after variables, so they are worked out
+    //   at the current precision and can still be shadowed. A named function
+    //   can also be used as a value, as in 'integrate(f, 0, 1)'.
+    fn lookup_var(&self, name: &str) -> Option<Value> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|s| s.borrow().vars.get(name).cloned())
+            .next()
+            .or_else(|| builtins::constant(name))
+            .or_else(|| self.lookup_closure(name).map(Value::Function))
+    }
+
+    // Assignment rebinds the innermost existing variable of that name.
+    //   If there is none, the variable is created in the current scope.
+    //   Inside a function body the globals are only rebound for state
+    //   variables, so a call can't clobber the REPL's variables by reusing a name.
+    fn assign_var(&mut self, name: String, val: Value) {
+        let first = if self.depth > 0 && !self.state.contains(&name) { 1 } else { 0 };
+
+        for s in self.scopes[first..].iter().rev() {
+            if let Some(v) = s.borrow_mut().vars.get_mut(&name) {
+                *v = val;
+                return;
+            }
+        }
+
+        self.scopes.last().unwrap().borrow_mut().vars.insert(name, val);
+    }
+
+    // Stores a value into the target of an assignment, which is either a variable
+    //   or an element of an array.
+    fn assign(&mut self, left: &Expr, val: Value) -> EvalResult<()> {
+        // Several targets, as in 'a, b = b, a'. The right side has already been
+        //   worked out in full, so swapping works.
+        if let Some(targets) = tuple_items(left) {
+            let vals = val
+                .unpack(targets.len())
+                .map_err(|e| RuntimeError::new(e, expr_pos(left)))?;
+
+            for (t, v) in targets.iter().zip(vals) {
+                self.assign(t, v)?;
+            }
+
+            return Ok(());
+        }
+
+        match left {
+            Expr::Index { target, index } => {
+                let t = self.eval(target)?;
+                let i = self.eval(index)?;
+                t.set_index(&i, val)
+                    .map_err(|e| RuntimeError::new(e, expr_pos(left)))
+            }
+            _ => {
+                let name = assign_target(left)?;
+                self.assign_var(name, val);
+                Ok(())
+            }
+        }
+    }
+
+    // Asks for a value for 'read' until one that reads as a literal is typed in.
+    //   Without a terminal there is nobody to ask again, so a bad value is an error.
+    fn read_value(&mut self, name: &str, pos: usize) -> EvalResult<Value> {
+        loop {
+            let line = match self.console.read_line(&format!("{}? ", name)) {
+                Some(l) => l,
+                None => return Err(RuntimeError::new(format!("No input left to read {} from", name), pos)),
+            };
+
+            let msg = match literal(line.trim()) {
+                Ok(e) => return self.eval(&e),
+                Err(msg) => msg,
+            };
+
+            if !self.console.is_interactive() {
+                return Err(RuntimeError::new(format!("Could not read {}: {}", name, msg), pos));
+            }
+
+            self.console.print(&msg);
+        }
+    }
+
+    // Runs a block of statements in a fresh scope.
+    fn exec_scope(&mut self, scope: &Scope) -> EvalResult<Flow> {
+        self.scopes.push(new_scope());
+        let res = self.exec_block(&scope.contents);
+        self.scopes.pop();
+
+        res
+    }
+
+    // Runs a list of statements in the current scope, stopping early on a return.
+    fn exec_block(&mut self, stmts: &[Statement]) -> EvalResult<Flow> {
+        for s in stmts {
+            match self.exec(s)? {
+                Flow::Next => {}
+                f => return Ok(f),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    // Statements count towards the nesting limit too, so blocks nested in a recursive
+    //   function are caught by the next expression they evaluate.
+    fn exec(&mut self, stmt: &Statement) -> EvalResult<Flow> {
+        self.nesting += 1;
+        let res = self.exec_node(stmt);
+        self.nesting -= 1;
+
+        res
+    }
+
+    fn exec_node(&mut self, stmt: &Statement) -> EvalResult<Flow> {
+        match stmt {
+            Statement::Expr(e) => {
+                self.eval(e)?;
+                Ok(Flow::Next)
+            }
+            Statement::Assign { left, right } => {
+                let val = self.eval(right)?;
+                self.assign(left, val)?;
+                Ok(Flow::Next)
+            }
+            Statement::CompoundAssign { left, op, right } => {
+                let pos = expr_pos(left);
+
+                // The target is worked out once, so 'a[f()] += 1' only calls f once.
+                match left {
+                    Expr::Index { target, index } => {
+                        let t = self.eval(target)?;
+                        let i = self.eval(index)?;
+                        let cur = t.index(&i).map_err(|e| RuntimeError::new(e, pos))?;
+                        let r = self.eval(right)?;
+                        let val = fit_word(binary_op(op, cur, r, pos)?, pos)?;
+
+                        t.set_index(&i, val).map_err(|e| RuntimeError::new(e, pos))?;
+                    }
+                    _ => {
+                        let name = assign_target(left)?;
+                        let cur = self.lookup_var(&name).ok_or_else(|| {
+                            RuntimeError::new(format!("Undefined variable {}", name), pos)
+                        })?;
+                        let r = self.eval(right)?;
+                        let val = fit_word(binary_op(op, cur, r, pos)?, pos)?;
+
+                        self.assign_var(name, val);
+                    }
+                }
+
+                Ok(Flow::Next)
+            }
+            Statement::ArrayDecl { name, value } => {
+                let val = match value {
+                    None => Value::array(Vec::new()),
+                    Some(e) => match self.eval(e)? {
+                        a @ Value::Array(_) => a,
+                        // A length gives that many zeros, up to the longest array that can be made.
+                        Value::Int(ref n) if !n.is_negative() => match n.to_usize().filter(|&n| n <= MAX_LENGTH) {
+                            Some(n) => Value::array(vec![Value::Int(BigInt::from(0)); n]),
+                            None => return Err(RuntimeError::new(
+                                format!("Array length {} is too large", n),
+                                expr_pos(e),
+                            )),
+                        },
+                        v => return Err(RuntimeError::new(
+                            format!("An array needs a list of items or a length, got {}", v.type_name()),
+                            expr_pos(e),
+                        )),
+                    },
+                };
+
+                // Declarations always make a new variable in the current scope.
+                let name = name.get_val().unwrap_as_string().unwrap_or_default();
+                self.scopes.last().unwrap().borrow_mut().vars.insert(name, val);
+                Ok(Flow::Next)
+            }
+            Statement::Return { val } => Ok(Flow::Return(self.eval(val)?)),
+            Statement::State { name, value } => {
+                let n = name.get_val().unwrap_as_string().unwrap_or_default();
+
+                // State variables are always global, wherever they are declared.
+                match value {
+                    Some(e) => {
+                        let val = self.eval(e)?;
+                        self.scopes[0].borrow_mut().vars.insert(n.clone(), val);
+                    }
+                    None => if !self.scopes[0].borrow().vars.contains_key(&n) {
+                        return Err(RuntimeError::new(format!("Undefined variable {}", n), name.get_pos()));
+                    },
+                }
+
+                if !self.state.contains(&n) {
+                    self.state.push(n);
+                }
+
+                Ok(Flow::Next)
+            }
+            Statement::While(b) => {
+                while truthy(&self.eval(&b.cond)?, expr_pos(&b.cond))? {
+                    if let Some(f) = self.exec_loop_body(&b.body)? {
+                        return Ok(f);
+                    }
+                }
+
+                Ok(Flow::Next)
+            }
+            Statement::For { target, iter, body } => {
+                // The items are taken up front, so changing an array inside
+                //   the loop does not change what the loop goes over.
+                let items: Vec<Value> = match self.eval(iter)? {
+                    // With several targets, a map gives pairs of keys and values.
+                    Value::Map(m) => if tuple_items(target).is_some() {
+                        m.borrow().iter().map(|e| Value::tuple(vec![e.0.clone(), e.1.clone()])).collect()
+                    } else {
+                        m.borrow().iter().map(|e| e.0.clone()).collect()
+                    },
+                    Value::Array(a) => a.borrow().clone(),
+                    Value::Tuple(t) => (*t).clone(),
+                    Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+                    v => return Err(RuntimeError::new(
+                        format!("Cannot loop over {}", v.type_name()),
+                        expr_pos(iter),
+                    )),
+                };
+
+                for item in items {
+                    self.assign(target, item)?;
+
+                    if let Some(f) = self.exec_loop_body(body)? {
+                        return Ok(f);
+                    }
+                }
+
+                Ok(Flow::Next)
+            }
+            Statement::Write(values) => {
+                let mut out = Vec::with_capacity(values.len());
+                for v in values {
+                    out.push(self.eval(v)?.to_string());
+                }
+
+                self.console.print(&out.join(" "));
+                Ok(Flow::Next)
+            }
+            Statement::Read(t) => {
+                let name = t.get_val().unwrap_as_string().unwrap_or_default();
+                let val = self.read_value(&name, t.get_pos())?;
+                self.assign_var(name, val);
+                Ok(Flow::Next)
+            }
+            Statement::Break(_) => Ok(Flow::Break),
+            Statement::Continue(_) => Ok(Flow::Continue),
+            Statement::Branch {
+                if_block,
+                alt_blocks,
+                else_block,
+            } => {
+                for b in ::std::iter::once(if_block).chain(alt_blocks.iter()) {
+                    if truthy(&self.eval(&b.cond)?, expr_pos(&b.cond))? {
+                        return self.exec(&b.body);
+                    }
+                }
+
+                match else_block {
+                    Some(b) => self.exec(b),
+                    None => Ok(Flow::Next),
+                }
+            }
+            Statement::FnDecl(f) => {
+                self.define_fn(f);
+                Ok(Flow::Next)
+            }
+            Statement::Scope(s) => self.exec_scope(s),
+            Statement::Error(e) => Err(RuntimeError::new(
+                format!("Cannot run a statement with a syntax error: {}", e.message()),
+                e.span.start,
+            )),
+        }
+    }
+
+    // Evaluates an expression. In programmer mode every integer result, down to
+    //   single literals and variables, is brought into the word size.
+    pub fn eval(&mut self, expr: &Expr) -> EvalResult<Value> {
+        if self.nesting >= MAX_EVAL_DEPTH {
+            return Err(RuntimeError::new("maximum recursion depth exceeded".to_owned(), expr_pos(expr)));
+        }
+
+        self.nesting += 1;
+        let v = self.eval_node(expr);
+        self.nesting -= 1;
+
+        fit_word(v?, expr_pos(expr))
+    }
+
+    fn eval_node(&mut self, expr: &Expr) -> EvalResult<Value> {
+        match expr {
+            Expr::Factor(f) => self.eval_factor(f),
+            Expr::Unary { right, op } => {
+                let pos = expr_pos(right);
+
+                // A negative literal is only brought into the word size once it has been
+                //   negated, so the smallest signed value, like -128 in an i8, can be typed.
+                let r = if *op == Op::Neg && is_int_literal(right) {
+                    self.eval_node(right)?
+                } else {
+                    self.eval(right)?
+                };
+
+                match (op, r) {
+                    (Op::Pos, r @ Value::Int(_))
+                    | (Op::Pos, r @ Value::Rational(_))
+                    | (Op::Pos, r @ Value::Float(_))
+                    | (Op::Pos, r @ Value::Decimal(_))
+                    | (Op::Pos, r @ Value::Complex(_)) => Ok(r),
+                    (Op::Neg, r) => (-r).map_err(|e| RuntimeError::new(e, pos)),
+                    (Op::BitNot, r) => r.bit_not().map_err(|e| RuntimeError::new(e, pos)),
+                    (Op::Not, r) => Ok(Value::Bool(!truthy(&r, pos)?)),
+                    (op, r) => Err(RuntimeError::new(
+                        format!("Cannot apply {} to {}", op.symbol(), r.type_name()),
+                        pos,
+                    )),
+                }
+            }
+            Expr::Binary { left, right, op } => {
+                let pos = expr_pos(left);
+
+                // The logical operators short circuit, so the right side is only evaluated when needed.
+                match op {
+                    Op::And => {
+                        return Ok(Value::Bool(
+                            truthy(&self.eval(left)?, pos)?
+                                && truthy(&self.eval(right)?, expr_pos(right))?,
+                        ))
+                    }
+                    Op::Or_ => {
+                        return Ok(Value::Bool(
+                            truthy(&self.eval(left)?, pos)?
+                                || truthy(&self.eval(right)?, expr_pos(right))?,
+                        ))
+                    }
+                    _ => {}
+                }
+
+                let l = self.eval(left)?;
+                let r = self.eval(right)?;
+
+                binary_op(op, l, r, pos)
+            }
+            Expr::Conditional { cond, if_true, if_false } => {
+                if truthy(&self.eval(cond)?, expr_pos(cond))? {
+                    self.eval(if_true)
+                } else {
+                    self.eval(if_false)
+                }
+            }
+            // Each operand is evaluated once, and the chain stops at the first comparison that fails.
+            Expr::Compare { first, rest } => {
+                let mut l = self.eval(first)?;
+                let mut pos = expr_pos(first);
+
+                for (op, e) in rest {
+                    let r = self.eval(e)?;
+
+                    if let Value::Bool(false) = binary_op(op, l, r.clone(), pos)? {
+                        return Ok(Value::Bool(false));
+                    }
+
+                    l = r;
+                    pos = expr_pos(e);
+                }
+
+                Ok(Value::Bool(true))
+            }
+            Expr::Call { target, args } => {
+                let pos = expr_pos(target);
+                let f = self.eval(target)?;
+                let (args, kwargs) = self.eval_args(args)?;
+
+                match f {
+                    Value::Function(c) => self.call_fn(c, args, kwargs, pos),
+                    v => Err(RuntimeError::new(format!("Cannot call {}", v.type_name()), pos)),
+                }
+            }
+            Expr::Index { target, index } => {
+                let t = self.eval(target)?;
+                let i = self.eval(index)?;
+
+                t.index(&i).map_err(|e| RuntimeError::new(e, expr_pos(index)))
+            }
+            Expr::Slice { target, start, end, step } => {
+                let t = self.eval(target)?;
+                let mut bounds = Vec::new();
+
+                for b in &[start, end, step] {
+                    bounds.push(match b {
+                        Some(e) => Some(self.eval(e)?),
+                        None => None,
+                    });
+                }
+
+                t.slice(bounds[0].as_ref(), bounds[1].as_ref(), bounds[2].as_ref())
+                    .map_err(|e| RuntimeError::new(e, expr_pos(target)))
+            }
+        }
+    }
+
+    fn eval_factor(&mut self, factor: &Factor) -> EvalResult<Value> {
+        match factor {
+            Factor::Int(t) | Factor::Float(t) | Factor::Imag(t) | Factor::String(t) | Factor::Bool(t) => {
+                Ok(Value::from_token(t.get_val()))
+            }
+            Factor::Symbol(t) => {
+                let name = t.get_val().unwrap_as_string().unwrap_or_default();
+                self.lookup_var(&name).ok_or_else(|| {
+                    RuntimeError::new(format!("Undefined variable {}", name), t.get_pos())
+                })
+            }
+            Factor::FnCall(c) => self.eval_fn_call(c),
+            Factor::Lambda(f) => Ok(Value::Function(self.closure(f, false))),
+            Factor::Array(a) => {
+                let mut items = Vec::with_capacity(a.items.len());
+                for e in &a.items {
+                    items.push(self.eval(e)?);
+                }
+
+                Ok(Value::array(items))
+            }
+            Factor::Map(m) => {
+                let mut entries = Vec::with_capacity(m.items.len());
+                for (k, v) in &m.items {
+                    let key = self.eval(k)?;
+                    let val = self.eval(v)?;
+                    Value::map_insert(&mut entries, key, val).map_err(|e| RuntimeError::new(e, expr_pos(k)))?;
+                }
+
+                Ok(Value::map(entries))
+            }
+            Factor::Tuple(t) => {
+                let mut items = Vec::with_capacity(t.items.len());
+                for e in &t.items {
+                    items.push(self.eval(e)?);
+                }
+
+                Ok(Value::tuple(items))
+            }
+            Factor::Expr(e) => self.eval(e),
+            Factor::None => Ok(Value::None),
+        }
+    }
+
+    fn eval_args(&mut self, list: &ArgList) -> EvalResult<Args> {
+        let mut args = Vec::with_capacity(list.argv.len());
+        for a in &list.argv {
+            args.push(self.eval(a)?);
+        }
+
+        let mut kwargs = Vec::with_capacity(list.kwargs.len());
+        for (name, a) in &list.kwargs {
+            kwargs.push((name.get_val().unwrap_as_string().unwrap_or_default(), self.eval(a)?));
+        }
+
+        Ok((args, kwargs))
+    }
+
+    // Calls a function by name. A variable holding a function comes first, so a
+    //   function passed in as an argument is called even if a global function
+    //   shares its name. Then come named functions, and finally builtins.
+    fn eval_fn_call(&mut self, call: &FnCall) -> EvalResult<Value> {
+        let name = call.name.get_val().unwrap_as_string().unwrap_or_default();
+        let pos = call.name.get_pos();
+
+        let (args, kwargs) = self.eval_args(&call.args)?;
+
+        if let Some(Value::Function(f)) = self.lookup_var(&name) {
+            return self.call_fn(f, args, kwargs, pos);
+        }
+
+        if !kwargs.is_empty() {
+            return Err(RuntimeError::new(format!("{} does not take keyword arguments", name), pos));
+        }
+
+        // Builtins like map() call back into the interpreter. An error inside the
+        //   function they were given is kept whole, so it points into that function.
+        let mut inner = None;
+        let res = {
+            let mut apply = |f: &Value, args: Vec<Value>| match f {
+                Value::Function(c) => self.call_fn(c.clone(), args, Vec::new(), pos).map_err(|e| {
+                    let msg = e.msg.clone();
+                    inner = Some(e);
+                    msg
+                }),
+                v => Err(format!("Expected a function, got {}", v.type_name())),
+            };
+
+            builtins::call(&name, args, &mut apply)
+        };
+
+        match res {
+            Some(Ok(v)) => Ok(v),
+            Some(Err(e)) => Err(inner.unwrap_or_else(|| RuntimeError::new(e, pos))),
+            None => Err(RuntimeError::new(format!("Undefined function {}", name), pos)),
+        }
+    }
+
+    // Calls a function. Any problem with the arguments is reported at pos, the call site.
+    fn call_fn(
+        &mut self,
+        f: Rc<Closure>,
+        args: Vec<Value>,
+        kwargs: Vec<(String, Value)>,
+        pos: usize,
+    ) -> EvalResult<Value> {
+        let name = f.name();
+        let params = &f.func.args;
+
+        if args.len() > params.argv.len() && params.rest.is_none() {
+            return Err(RuntimeError::new(
+                format!(
+                    "{} takes {}{} arguments but {} were given",
+                    name,
+                    if params.argv.iter().any(|p| p.default.is_some()) { "at most " } else { "" },
+                    params.argv.len(),
+                    args.len()
+                ),
+                pos,
+            ));
+        }
+
+        // Positional arguments fill the parameters in order, and any left over go to
+        //   '*args'. Keyword arguments then fill parameters by name.
+        let mut slots: Vec<Option<Value>> = vec![None; params.argv.len()];
+        let mut rest = Vec::new();
+
+        for (i, v) in args.into_iter().enumerate() {
+            match slots.get_mut(i) {
+                Some(s) => *s = Some(v),
+                None => rest.push(v),
+            }
+        }
+
+        for (k, v) in kwargs {
+            let msg = match params.argv.iter().position(|p| token_name(&p.name) == k) {
+                Some(i) if slots[i].is_none() => {
+                    slots[i] = Some(v);
+                    continue;
+                }
+                Some(_) => format!("{} was given {} twice", name, k),
+                None => format!("{} has no parameter named {}", name, k),
+            };
+
+            return Err(RuntimeError::new(msg, pos));
+        }
+
+        if let Some(p) = params
+            .argv
+            .iter()
+            .zip(&slots)
+            .find(|&(p, s)| s.is_none() && p.default.is_none())
+        {
+            return Err(RuntimeError::new(
+                format!("{} is missing a value for {}", name, token_name(&p.0.name)),
+                pos,
+            ));
+        }
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::new("maximum recursion depth exceeded".to_owned(), pos));
+        }
+
+        let mut frame = Frame::default();
+
+        if let Some(ref r) = params.rest {
+            frame.vars.insert(token_name(r), Value::tuple(rest));
+        }
+
+        // Bind the function to its own name so it can always call itself, even
+        //   from inside a scope that has another function of that name.
+        if f.named {
+            frame.fns.insert(name, f.clone());
+        }
+
+        let caller = self.scopes.split_off(1);
+        self.scopes.extend(f.env.iter().cloned());
+        self.scopes.push(Rc::new(RefCell::new(frame)));
+        self.depth += 1;
+        let res = self
+            .bind_params(&params.argv, slots)
+            .and_then(|_| self.exec_block(&f.func.body.contents));
+        self.depth -= 1;
+        self.scopes.truncate(1);
+        self.scopes.extend(caller);
+
+        // Positions in errors from the body are in the source the function was written in.
+        match res.map_err(|e| e.in_source(&f.source))? {
+            Flow::Return(v) => Ok(v),
+            _ => Ok(Value::None),
+        }
+    }
+
+    // Puts the arguments of a call into the function's scope, which is the current one.
+    //   Defaults are worked out here, in order, so a default can use the parameters
+    //   before it, as in 'fn f(x, y = x * 2)'.
+    fn bind_params(&mut self, params: &[Param], slots: Vec<Option<Value>>) -> EvalResult<()> {
+        for (p, s) in params.iter().zip(slots) {
+            let val = match (s, &p.default) {
+                (Some(v), _) => v,
+                (None, Some(d)) => self.eval(d)?,
+                (None, None) => Value::None,
+            };
+
+            self.scopes.last().unwrap().borrow_mut().vars.insert(token_name(&p.name), val);
+        }
+
+        Ok(())
+    }
+
+    // Runs the body of a loop once. Returns None if the loop should stop,
+    //   which happens on a 'break' or a 'return'.
+    fn exec_loop_body(&mut self, body: &Statement) -> EvalResult<Option<Flow>> {
+        match self.exec(body)? {
+            Flow::Next | Flow::Continue => Ok(None),
+            Flow::Break => Ok(Some(Flow::Next)),
+            f => Ok(Some(f)),
+        }
+    }
+}
+
+// Parses text typed in for 'read'. Only literals are accepted, so typing a
+//   variable or a function call does not run anything.
+fn literal(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser::new();
+    parser.input(text.to_owned());
+
+    let e = parser.parse_expr().map_err(|e| e.message())?;
+
+    if is_literal(&e) {
+        Ok(e)
+    } else {
+        Err(format!("'{}' is not a number, string, array or map", text))
+    }
+}
+
+fn is_literal(e: &Expr) -> bool {
+    match e {
+        Expr::Unary { op: Op::Pos, right } | Expr::Unary { op: Op::Neg, right } => is_literal(right),
+        // Fractions and complex numbers are written as '-2/3' and '1+2j', so those
+        //   read back too.
+        Expr::Binary { op: Op::Div, left, right } => is_signed(left, is_int_literal) && is_int_literal(right),
+        Expr::Binary { op: Op::Add, left, right } | Expr::Binary { op: Op::Sub, left, right } => {
+            is_signed(left, is_real_literal) && is_imag_literal(right)
+        }
+        Expr::Factor(f) => match **f {
+            Factor::Int(_) | Factor::Float(_) | Factor::Imag(_) | Factor::String(_) | Factor::Bool(_) => true,
+            Factor::Array(ref a) => a.items.iter().all(is_literal),
+            Factor::Map(ref m) => m.items.iter().all(|e| is_literal(&e.0) && is_literal(&e.1)),
+            Factor::Tuple(ref t) => t.items.iter().all(is_literal),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Whether an expression is a literal accepted by 'is_kind', with any number of signs in front.
+fn is_signed(e: &Expr, is_kind: fn(&Expr) -> bool) -> bool {
+    match e {
+        Expr::Unary { op: Op::Pos, right } | Expr::Unary { op: Op::Neg, right } => is_signed(right, is_kind),
+        e => is_kind(e),
+    }
+}
+
+fn is_real_literal(e: &Expr) -> bool {
+    match e {
+        Expr::Factor(f) => matches!(**f, Factor::Int(_) | Factor::Float(_)),
+        _ => false,
+    }
+}
+
+fn is_imag_literal(e: &Expr) -> bool {
+    match e {
+        Expr::Factor(f) => matches!(**f, Factor::Imag(_)),
+        _ => false,
+    }
+}
+
+fn is_int_literal(e: &Expr) -> bool {
+    match e {
+        Expr::Factor(f) => matches!(**f, Factor::Int(_)),
+        _ => false,
+    }
+}
+
+// Brings an integer result into the word size, when one is set.
+fn fit_word(val: Value, pos: usize) -> EvalResult<Value> {
+    match (val, word::word()) {
+        (Value::Int(x), Some(w)) => w
+            .fit(x)
+            .map(Value::Int)
+            .map_err(|e| RuntimeError::new(e, pos)),
+        (v, _) => Ok(v),
+    }
+}
+
+// Applies a binary operator to two evaluated operands.
+fn binary_op(op: &Op, l: Value, r: Value, pos: usize) -> EvalResult<Value> {
+    let res: ValueResult = match op {
+        Op::Add => l + r,
+        Op::Sub => l - r,
+        Op::Mul => l * r,
+        Op::Div => l / r,
+        Op::Mod => l % r,
+        Op::IntDiv => l.floor_div(r),
+        Op::Pow => l.pow(r),
+        Op::BitAnd => l & r,
+        Op::BitOr => l | r,
+        Op::BitXor => l ^ r,
+        Op::Lls => l << r,
+        Op::Ars => l >> r,
+        Op::Lrs => l.logical_shr(r),
+        Op::Eq_ => Ok(Value::Bool(l == r)),
+        Op::Neq => Ok(Value::Bool(l != r)),
+        Op::Lt_ | Op::Leq | Op::Gt_ | Op::Geq => match l.compare(&r) {
+            Some(o) => Ok(Value::Bool(match op {
+                Op::Lt_ => o == Ordering::Less,
+                Op::Leq => o != Ordering::Greater,
+                Op::Gt_ => o == Ordering::Greater,
+                _ => o != Ordering::Less,
+            })),
+            None => Err(format!(
+                "Cannot compare {} and {}",
+                l.type_name(),
+                r.type_name()
+            )),
+        },
+        _ => Err(format!("{} is not supported yet", op.symbol())),
+    };
+
+    res.map_err(|e| RuntimeError::new(e, pos))
+}
+
+fn truthy(val: &Value, pos: usize) -> EvalResult<bool> {
+    val.truthy().ok_or_else(|| {
+        RuntimeError::new(
+            format!("Expected a condition, got {}", val.type_name()),
+            pos,
+        )
+    })
+}
+
+// The name held by a variable token.
+fn token_name(t: &TokStruct) -> String {
+    t.get_val().unwrap_as_string().unwrap_or_default()
+}
+
+// The targets of an assignment to several things at once, as in 'a, b = 1, 2'.
+fn tuple_items(left: &Expr) -> Option<&[Expr]> {
+    match left {
+        Expr::Factor(f) => match **f {
+            Factor::Tuple(ref t) => Some(&t.items),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn assign_target(left: &Expr) -> EvalResult<String> {
+    match left {
+        Expr::Factor(f) => match **f {
+            Factor::Symbol(ref t) => Ok(t.get_val().unwrap_as_string().unwrap_or_default()),
+            _ => Err(RuntimeError::new(
+                "Can only assign to a variable".to_owned(),
+                expr_pos(left),
+            )),
+        },
+        _ => Err(RuntimeError::new(
+            "Can only assign to a variable".to_owned(),
+            expr_pos(left),
+        )),
+    }
+}
+
+// Finds the position of the leftmost token in an expression, for error reporting.
+fn expr_pos(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binary { left, .. } => expr_pos(left),
+        Expr::Compare { first, .. } => expr_pos(first),
+        Expr::Call { target, .. } => expr_pos(target),
+        Expr::Conditional { if_true, .. } => expr_pos(if_true),
+        Expr::Unary { right, .. } => expr_pos(right),
+        Expr::Index { target, .. } | Expr::Slice { target, .. } => expr_pos(target),
+        Expr::Factor(f) => match **f {
+            Factor::Int(ref t)
+            | Factor::Float(ref t)
+            | Factor::Imag(ref t)
+            | Factor::String(ref t)
+            | Factor::Symbol(ref t)
+            | Factor::Bool(ref t) => t.get_pos(),
+            Factor::FnCall(ref c) => c.name.get_pos(),
+            Factor::Lambda(ref f) => f.name.get_pos(),
+            Factor::Array(ref a) => a.start.get_pos(),
+            Factor::Map(ref m) => m.start.get_pos(),
+            Factor::Tuple(ref t) => t.start.get_pos(),
+            Factor::Expr(ref e) => expr_pos(e),
+            Factor::None => 0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // Runs REPL lines one at a time and gives the value of the last one, or the
+    //   first error. Like the real interpreter, it runs on a thread with a big stack.
+    fn run(src: &str) -> Result<String, String> {
+        run_with(|| {}, src)
+    }
+
+    // Like 'run', but first calls 'setup' on the thread, where settings like the
+    //   word size are kept.
+    fn run_with(setup: fn(), src: &str) -> Result<String, String> {
+        let src = src.to_owned();
+
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                setup();
+
+                let mut interp = Interpreter::new(Console::plain());
+                let mut parser = Parser::new();
+                let mut last = None;
+
+                for line in src.lines() {
+                    parser.input(line.to_owned());
+                    interp.set_source(line);
+                    let stmts = parser.parse_repl_line().map_err(|e| e[0].message())?;
+                    last = interp.exec_line(&stmts).map_err(|e| e.msg)?;
+                }
+
+                Ok(last.map(|v| v.repr()).unwrap_or_default())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn array_lengths_are_limited() {
+        assert_eq!(run("array a = 3\na"), Ok("[0, 0, 0]".to_owned()));
+        assert_eq!(run("array a = 10**13"), Err("Array length 10000000000000 is too large".to_owned()));
+        assert_eq!(run("array a = 10**30"), Err("Array length 1000000000000000000000000000000 is too large".to_owned()));
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() {
+        assert_eq!(
+            run("fn f(n) { return f(n + 1); }\nf(0)"),
+            Err("maximum recursion depth exceeded".to_owned())
+        );
+        assert_eq!(run("fn g(n) { return 0 if n == 0 else 1 + g(n - 1); }\ng(900)"), Ok("900".to_owned()));
+    }
+
+    #[test]
+    fn deep_expressions_in_recursion_are_an_error() {
+        let negated = format!("fn f(n) {{ return {}f(n + 1); }}\nf(0)", "-".repeat(250));
+        let parens = format!("fn f(n) {{ return {}f(n + 1){}; }}\nf(0)", "(".repeat(120), ")".repeat(120));
+        let blocks = format!("fn f(n) {{ {}return f(n + 1);{} }}\nf(0)", "if true { ".repeat(120), " }".repeat(120));
+
+        for src in &[negated, parens, blocks] {
+            assert_eq!(run(src), Err("maximum recursion depth exceeded".to_owned()));
+        }
+    }
+
+    #[test]
+    fn integrate_and_solve() {
+        assert_eq!(run("integrate(|x| x ** 2, 0, 3)"), Ok("9.0".to_owned()));
+        assert_eq!(run("round(integrate(|x| sqrt(x), 0, 1) * 1e9)"), Ok("666666667".to_owned()));
+        assert_eq!(run("solve(|x| x * x - 2, 1) ** 2 - 2 < 1e-12"), Ok("true".to_owned()));
+        assert_eq!(
+            run("integrate(|x| sin(1000 * x), 0, 100)"),
+            Err("integrate did not converge".to_owned())
+        );
+    }
+
+    #[test]
+    fn repl_lines_show_only_expression_values() {
+        let shown = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| {
+                let mut interp = Interpreter::new(Console::plain());
+                let mut parser = Parser::new();
+                let mut shown = Vec::new();
+
+                for line in &["2 + 3", "x = 4", "fn sq(n) { return n * n; }", "sq(x) + 1", "x = x + 1; x", "y = sq"] {
+                    parser.input(line.to_string());
+                    interp.set_source(line);
+                    let stmts = parser.parse_repl_line().unwrap();
+                    shown.push(interp.exec_line(&stmts).unwrap().map(|v| v.to_string()));
+                }
+
+                shown
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let expected: Vec<Option<&str>> = vec![Some("5"), None, None, Some("17"), Some("5"), None];
+        assert_eq!(shown, expected.iter().map(|s| s.map(str::to_owned)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn conditionals_give_one_side() {
+        for &(src, val) in &[
+            ("1 if true else 2", "1"), ("1 if 0 else 2", "2"), ("(1 if false else 2) + 3", "5"),
+            ("x = -3\nx if x > 0 else -x", "3"), ("1 if true else undefined", "1"),
+            ("-1 if -2 < 0 else 0 if false else 1", "-1"),
+            ("y = if 2 > 1 { \"a\" } else { \"b\" }\ny", "\"a\""),
+            ("y = if false { 1 } elif true { 2 } else { 3 }\ny", "2"),
+            ("y = if false { 1 } else if false { 2 } else { 3 }\ny", "3"),
+            ("(if true { 1 } else { 1/0 }) * 3", "3"),
+            ("sign = fn(v) => if v > 0 { 1 } else { -1 if v < 0 else 0 }\n[sign(5), sign(0), sign(-5)]", "[1, 0, -1]"),
+            ("f = |v| if v < 0 { -v } else { v }\nf(-4)", "4"),
+        ] {
+            assert_eq!(run(src), Ok(val.to_owned()), "{}", src);
+        }
+
+        assert_eq!(run("y = if true { 1 }"), Err("expected keyword 'else', found end of input".to_owned()));
+        assert_eq!(run("y = if false { 1 } else { 1/0 }"), Err("Division by zero".to_owned()));
+    }
+
+    #[test]
+    fn tuples_unpack() {
+        for &(src, val) in &[
+            ("a, b = 1, 2\na, b = b, a\n(a, b)", "(2, 1)"), ("t = (1, 2, 3)\n[t[0], t[-1], len(t)]", "[1, 3, 3]"),
+            ("x, y, z = (1, 2, 3)\nz", "3"), ("p, q = [4, 5]\nq", "5"), ("()", "()"), ("(1,)", "(1,)"),
+            ("(1, 2) == (1, 2)", "true"), ("(1, 2) == (2, 1)", "false"),
+            ("fn f(n, d) { return n // d, n % d; }\nf(7, 2)", "(3, 1)"),
+            ("fn f(n, d) { return n // d, n % d; }\nq, r = f(7, 2)\nr", "1"),
+            ("s = 0\nfor k, v in [(1, 2), (3, 4)] { s = s + k * v; }\ns", "14"),
+        ] {
+            assert_eq!(run(src), Ok(val.to_owned()), "{}", src);
+        }
+
+        for &(src, err) in &[
+            ("a, b = 1, 2, 3", "Expected 2 values to unpack, got 3"),
+            ("a, b = 5", "Cannot unpack int"),
+            ("t = (1, 2)\nt[0] = 5", "Tuples cannot be changed; use an array instead"),
+        ] {
+            assert_eq!(run(src), Err(err.to_owned()), "{}", src);
+        }
+    }
+
+    #[test]
+    fn arrays_index_slice_and_grow() {
+        let a = "a = [1, 2, 3, 4]\n";
+
+        for &(src, val) in &[
+            ("a[0]", "1"), ("a[-1]", "4"), ("a[1:3]", "[2, 3]"), ("a[::-1]", "[4, 3, 2, 1]"),
+            ("a[::2]", "[1, 3]"), ("a[2] = 9\na", "[1, 2, 9, 4]"), ("push(a, 5)\na", "[1, 2, 3, 4, 5]"),
+            ("pop(a)", "4"), ("pop(a)\na", "[1, 2, 3]"), ("len(a)", "4"), ("sum(a)", "10"),
+            ("b = a\npush(b, 5)\na", "[1, 2, 3, 4, 5]"), ("sort([3, 1, 2])", "[1, 2, 3]"),
+            ("array b\nb", "[]"), ("array b = [1]\nb", "[1]"), ("\"abc\"[1]", "\"b\""),
+        ] {
+            assert_eq!(run(&format!("{}{}", a, src)), Ok(val.to_owned()), "{}", src);
+        }
+
+        for &(src, err) in &[
+            ("a[4]", "Array index 4 is out of range for length 4"),
+            ("a[-5]", "Array index -5 is out of range for length 4"),
+            ("a[4] = 1", "Array index 4 is out of range for length 4"),
+            ("a[\"x\"]", "Indexes must be integers, not string"),
+            ("[][0]", "Array index 0 is out of range for length 0"),
+            ("\"abc\"[5]", "String index 5 is out of range for length 3"),
+            ("pop([])", "pop from an empty array"),
+        ] {
+            assert_eq!(run(&format!("{}{}", a, src)), Err(err.to_owned()), "{}", src);
+        }
+    }
+
+    #[test]
+    fn complex_numbers_work() {
+        for &(src, val) in &[
+            ("3j", "3j"), ("2.5i", "2.5j"), ("-0.5j", "-0.5j"), ("(1+2j)*(3-1j)", "5+5j"),
+            ("(1+1j)/(1-1j)", "1j"), ("1j**2", "-1+0j"), ("sqrt(-4)", "2j"), ("re(1+2j)", "1.0"),
+            ("im(1+2j)", "2.0"), ("conj(1+2j)", "1-2j"), ("abs(3+4j)", "5.0"), ("arg(1j)", "1.5707963267948966"),
+            ("1 + 1j == 1+1j", "true"), ("j = 5\n2j + j", "5+2j"),
+        ] {
+            assert_eq!(run(src), Ok(val.to_owned()), "{}", src);
+        }
+
+        assert_eq!(run("1/0j"), Err("Division by zero".to_owned()));
+        assert_eq!(run("3j < 1j"), Err("Cannot compare complex and complex".to_owned()));
+    }
+
+    // Runs REPL lines with a console that reads the given input, and gives what
+    //   was printed along with the value of the last line or the first error.
+    fn run_scripted(src: &str, input: &[&str]) -> (Result<String, String>, Vec<String>) {
+        let (src, input) = (src.to_owned(), input.iter().map(|l| l.to_string()).collect::<Vec<_>>());
+
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let lines: Vec<&str> = input.iter().map(|l| l.as_str()).collect();
+                let mut interp = Interpreter::new(Console::scripted(&lines));
+                let mut parser = Parser::new();
+                let mut last = Ok(None);
+
+                for line in src.lines() {
+                    parser.input(line.to_owned());
+                    interp.set_source(line);
+                    last = match parser.parse_repl_line() {
+                        Ok(stmts) => interp.exec_line(&stmts).map_err(|e| e.msg),
+                        Err(e) => Err(e[0].message()),
+                    };
+                    if last.is_err() {
+                        break;
+                    }
+                }
+
+                let last = last.map(|v| v.map(|v| v.repr()).unwrap_or_default());
+                (last, interp.console().printed().to_vec())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn written_values_read_back() {
+        let x = r#"x = [1, -2/3, 2.5e-3, "a \"b\"", (true, false), {1: 3j, 2: 1-0.5j}, (4,)]"#;
+        let written = r#"[1, -2/3, 0.0025, "a \"b\"", (true, false), {1: 3j, 2: 1-0.5j}, (4,)] done"#;
+
+        let (res, printed) = run_scripted(&format!("{}\nwrite x, \"done\"", x), &[]);
+        assert_eq!(res, Ok(String::new()));
+        assert_eq!(printed, vec![written.to_owned()]);
+
+        let typed = written.trim_end_matches(" done");
+        let (res, printed) = run_scripted(&format!("{}\nread y\ny == x", x), &[typed]);
+        assert_eq!(res, Ok("true".to_owned()));
+        assert!(printed.is_empty());
+
+        assert_eq!(run_scripted("read n\nn * 2", &["  21\n"]).0, Ok("42".to_owned()));
+        assert_eq!(run_scripted("read s\ns", &["'hi'"]).0, Ok("\"hi\"".to_owned()));
+        assert_eq!(run_scripted("read n", &[]).0, Err("No input left to read n from".to_owned()));
+        assert!(run_scripted("read n", &["1 +"]).0.unwrap_err().starts_with("Could not read n: "));
+        assert!(run_scripted("read n", &["f(1)"]).0.unwrap_err().starts_with("Could not read n: "));
+        assert!(run_scripted("read n", &["1/x"]).0.unwrap_err().starts_with("Could not read n: "));
+        assert!(run_scripted("read n", &["1+2"]).0.unwrap_err().starts_with("Could not read n: "));
+    }
+
+    #[test]
+    fn round_takes_halves_to_even() {
+        for &(src, rounded) in &[
+            ("round(2.5)", "2"), ("round(-2.5)", "-2"), ("round(3.5)", "4"), ("round(-3.5)", "-4"),
+            ("round(2.6)", "3"), ("round(-2.4)", "-2"), ("round(float(2.5))", "2"),
+            ("round(float(-2.5))", "-2"), ("round(float(0.5))", "0"), ("round(float(1.5))", "2"),
+        ] {
+            assert_eq!(run(src), Ok(rounded.to_owned()), "{}", src);
+        }
+    }
+
+    #[test]
+    fn huge_ranges_are_errors() {
+        assert_eq!(run("range(10, 0, -3)"), Ok("[10, 7, 4, 1]".to_owned()));
+        assert_eq!(run("range(10**10, 0)"), Ok("[]".to_owned()));
+        assert_eq!(run("range(10**10)"), Err("range of 10000000000 items is too long".to_owned()));
+        assert_eq!(
+            run("s = 0\nfor i in range(10**10) { s = s + i }"),
+            Err("range of 10000000000 items is too long".to_owned())
+        );
+    }
+
+    #[test]
+    fn huge_shifts_are_errors() {
+        assert_eq!(run("1 << 64"), Ok("18446744073709551616".to_owned()));
+        assert_eq!(run("1 << 10**11"), Err("Shift count 100000000000 is too large".to_owned()));
+        assert_eq!(run("1 >> 10**11"), Err("Shift count 100000000000 is too large".to_owned()));
+    }
+
+    #[test]
+    fn smallest_signed_word_can_be_typed() {
+        fn i8_checked() {
+            word::set_word(word::Word::from_name("i8"));
+            word::set_overflow(word::Overflow::Check);
+        }
+
+        assert_eq!(run_with(i8_checked, "x = -128\nx"), Ok("-128".to_owned()));
+        assert_eq!(run_with(i8_checked, "x = -(128)\nx"), Ok("-128".to_owned()));
+        assert_eq!(
+            run_with(i8_checked, "x = 128"),
+            Err("Overflow: 128 does not fit in i8".to_owned())
+        );
+        assert_eq!(
+            run_with(i8_checked, "x = -129"),
+            Err("Overflow: -129 does not fit in i8".to_owned())
+        );
+        assert_eq!(
+            run_with(i8_checked, "x = -128\nx - 1"),
+            Err("Overflow: -129 does not fit in i8".to_owned())
+        );
+    }
+
+    // Runs REPL lines and gives the state file that would be saved after them.
+    fn saved_state(src: &str) -> String {
+        let mut interp = Interpreter::new(Console::plain());
+        let mut parser = Parser::new();
+
+        for line in src.lines() {
+            parser.input(line.to_owned());
+            let stmts = parser.parse_repl_line().unwrap();
+            interp.exec_line(&stmts).unwrap();
+        }
+
+        interp.save_state()
+    }
+
+    #[test]
+    fn state_reads_back() {
+        let state = saved_state(
+            "state a = [1, 2/3, 0.5, \"x\\n\"]\n\
+             state m = {\"k\": (1,), 2: 1+2j}\n\
+             state f = |x| x\n\
+             state i = 1e308 * 10\n\
+             state n = [[0.5, |x| x]]\n\
+             state t = (true, 7)",
+        );
+
+        assert_eq!(
+            state,
+            "state a = [1, 2/3, 0.5, \"x\\n\"];\n\
+             state m = {\"k\": (1,), 2: 1+2j};\n\
+             state t = (true, 7);\n"
+        );
+        assert_eq!(
+            run(&format!("{}[a, m, t]", state)),
+            Ok("[[1, 2/3, 0.5, \"x\\n\"], {\"k\": (1,), 2: 1+2j}, (true, 7)]".to_owned())
+        );
+    }
+
+    #[test]
+    fn unary_plus_keeps_any_number() {
+        assert_eq!(run("+(1/3)"), Ok("1/3".to_owned()));
+        assert_eq!(run("+2j"), Ok("2j".to_owned()));
+        assert_eq!(run("+(1+2j)"), Ok("1+2j".to_owned()));
+        assert_eq!(run("+\"a\""), Err("Cannot apply + to string".to_owned()));
+
+        fn decimals() {
+            ::decimal::set_precision(Some(20));
+        }
+
+        assert_eq!(run_with(decimals, "+(1 / 4.0)"), Ok("0.25".to_owned()));
+    }
+
+    #[test]
+    fn functions_do_not_overwrite_globals() {
+        assert_eq!(
+            run("i = 100\nfn f() { for i in range(3) { }; return i; }\n[f(), i]"),
+            Ok("[2, 100]".to_owned())
+        );
+        assert_eq!(
+            run("n = 1\nfn f() { n = n + 1; return n; }\n[f(), f(), n]"),
+            Ok("[2, 2, 1]".to_owned())
+        );
+
+        // Closures still update the variables they captured, and state variables
+        //   are global wherever they are assigned.
+        assert_eq!(
+            run("fn counter() { c = 0; return fn() { c += 1; return c; }; }\nk = counter()\n[k(), k()]"),
+            Ok("[1, 2]".to_owned())
+        );
+        assert_eq!(
+            run("state s = 1\nfn f() { s = s + 1; return s; }\n[f(), s]"),
+            Ok("[2, 2]".to_owned())
+        );
+    }
+
+    #[test]
+    fn operators_follow_python_precedence() {
+        assert_eq!(run("1 + 2 * 3 - 4 / 2"), Ok("5".to_owned()));
+        assert_eq!(run("2 ** 3 ** 2"), Ok("512".to_owned()));
+        assert_eq!(run("-2 ** 2"), Ok("-4".to_owned()));
+        assert_eq!(run("-7 // 2 * 2 + -7 % 2"), Ok("-7".to_owned()));
+        assert_eq!(run("1 | 2 ^ 3 & 4"), Ok("3".to_owned()));
+    }
+
+    #[test]
+    fn comparisons_chain() {
+        assert_eq!(run("1 < 2 < 3"), Ok("true".to_owned()));
+        assert_eq!(run("3 > 2 > 2"), Ok("false".to_owned()));
+        assert_eq!(run("1 < 3 > 2"), Ok("true".to_owned()));
+        assert_eq!(run("1 < 2 <= 2 != 3"), Ok("true".to_owned()));
+
+        // The middle is evaluated once, and nothing after a false link is evaluated at all.
+        assert_eq!(run("c = []\nfn f() { push(c, 1); return 2; }\n1 < f() < 3\nlen(c)"), Ok("1".to_owned()));
+        assert_eq!(run("c = []\nfn f() { push(c, 1); return 2; }\n5 < f() < f()\nlen(c)"), Ok("1".to_owned()));
+    }
+
+    #[test]
+    fn words_wrap_or_overflow() {
+        fn u8_wrapping() {
+            word::set_word(word::Word::from_name("u8"));
+        }
+
+        fn i8_wrapping() {
+            word::set_word(word::Word::from_name("i8"));
+        }
+
+        assert_eq!(run_with(u8_wrapping, "255 + 1"), Ok("0".to_owned()));
+        assert_eq!(run_with(u8_wrapping, "0 - 1"), Ok("255".to_owned()));
+        assert_eq!(run_with(u8_wrapping, "x = 200\nx += 100\nx"), Ok("44".to_owned()));
+        assert_eq!(run_with(i8_wrapping, "127 + 1"), Ok("-128".to_owned()));
+        assert_eq!(run_with(i8_wrapping, "~0"), Ok("-1".to_owned()));
+        assert_eq!(run_with(i8_wrapping, "64 * 4"), Ok("0".to_owned()));
+
+        // Floats are left alone.
+        assert_eq!(run_with(u8_wrapping, "300.5"), Ok("300.5".to_owned()));
+    }
+
+    #[test]
+    fn errors_in_functions_keep_their_source() {
+        let err = thread::spawn(|| {
+            let mut interp = Interpreter::new(Console::plain());
+            let mut parser = Parser::new();
+            let mut err = None;
+
+            for line in &["fn f(x) { return 1/x; }", "f(0) + len(\"éééééééé\")"] {
+                parser.input(line.to_string());
+                interp.set_source(line);
+                let stmts = parser.parse_repl_line().unwrap();
+                err = interp.exec_line(&stmts).err();
+            }
+
+            err.map(|e| (e.msg, e.pos, e.source.map(|s| s.to_string())))
+        }).join().unwrap();
+
+        assert_eq!(
+            err,
+            Some(("Division by zero".to_owned(), 17, Some("fn f(x) { return 1/x; }".to_owned())))
+        );
+    }
+
+    #[test]
+    fn lambdas_do_not_hide_a_function_called_lambda() {
+        assert_eq!(run("fn lambda(x) { return 99; }\ng = |n| lambda(n)\ng(1)"), Ok("99".to_owned()));
+    }
+}
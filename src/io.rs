@@ -6,8 +6,10 @@ use termion::input::TermRead;
 use termion::event::Key;
 use termion::cursor::Goto;
 
-use std::collections::BTreeMap;
-use std::io::{Stdin, Stdout, Write};
+use diagnostic::Diagnostic;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, Stdin, Stdout, Write};
 
 pub struct Point {
     pub x: u16,
@@ -16,7 +18,7 @@ pub struct Point {
 
 impl Point {
     pub fn new(x: u16, y: u16) -> Point {
-        Point { x: x, y: y }
+        Point { x, y }
     }
 }
 
@@ -89,15 +91,15 @@ impl Trie {
         unsafe {
             let mut m: *mut Trie = &mut trie as *mut Trie;
             let d = string.as_bytes();
-            for i in 0..string.len() {
-                m = match (*m).children.get_mut(&(d[i] as char)) {
+            for (i, &c) in d.iter().enumerate() {
+                m = match (*m).children.get_mut(&(c as char)) {
                     Some(x) => x as *mut Trie,
                     None => {
                         (*m).children.insert(
-                            d[i] as char,
+                            c as char,
                             Trie::new().val(&String::from(string.get(..=i).unwrap())),
                         );
-                        (*m).children.get_mut(&(d[i] as char)).unwrap()
+                        (*m).children.get_mut(&(c as char)).unwrap()
                     }
                 }
             }
@@ -143,9 +145,25 @@ impl InputManager {
         ).unwrap();
     }
 
+    // Prints some text starting at the current line, moving the prompt down past it.
+    pub fn print(&mut self, output: &str) {
+        for l in output.lines() {
+            // The terminal is in raw mode, so every line needs an explicit carriage return.
+            write!(self.terminal, "\r{}{}\r\n", termion::clear::CurrentLine, l).unwrap();
+            self.pos.y += 1;
+        }
+
+        self.terminal.flush().unwrap();
+    }
+
+    // Prints a diagnostic for the given source below the current line, in colour.
+    pub fn put_diagnostic(&mut self, source: &str, diagnostic: &Diagnostic) {
+        self.print(&diagnostic.render_coloured(source).join("\n"));
+    }
+
     // Clears everything on the screen
     pub fn clear_all(&mut self) {
-        write!(self.terminal, "{}{}", termion::clear::All, Goto(1, 1));
+        write!(self.terminal, "{}{}", termion::clear::All, Goto(1, 1)).unwrap();
     }
 
     // The heart of this library. It reads a line until a
@@ -219,7 +237,7 @@ impl InputManager {
                                 &c.to_string(),
                                 &scribe::buffer::Position {
                                     line: 0,
-                                    offset: (self.pos.x - pl as u16 - 1) as usize,
+                                    offset: (self.pos.x - pl - 1) as usize,
                                 },
                             );
                         }
@@ -274,7 +292,7 @@ impl InputManager {
                                 &c.to_string(),
                                 &scribe::buffer::Position {
                                     line: 0,
-                                    offset: (self.pos.x - pl as u16) as usize,
+                                    offset: (self.pos.x - pl) as usize,
                                 },
                             );
                         }
@@ -317,7 +335,7 @@ impl InputManager {
                             // This first if block stores anything freshly typed in the buffer.
                             if h_index == self.history.len()
                                 && (!out.to_string().chars().all(|c: char| {
-                                    (c == ' ' || c == '\n' || c == '\r' || c == '\t')
+                                    c == ' ' || c == '\n' || c == '\r' || c == '\t'
                                 })
                                     || !out.to_string().is_empty())
                             {
@@ -349,7 +367,7 @@ impl InputManager {
                             Goto(1, self.pos.y),
                             prompt,
                             Goto(pl + 1, self.pos.y),
-                            out.to_string(),
+                            out,
                             Goto(self.pos.x + 1, self.pos.y)
                         ).unwrap();
                     }
@@ -393,7 +411,7 @@ impl InputManager {
                             } else {
                                 h_index = self.history.len();
                                 out.insert(
-                                    &String::new(),
+                                    "",
                                     &scribe::buffer::Position { line: 0, offset: 0 },
                                 );
                             }
@@ -407,7 +425,7 @@ impl InputManager {
                             Goto(1, self.pos.y),
                             prompt,
                             Goto(pl + 1, self.pos.y),
-                            out.to_string(),
+                            out,
                             Goto(self.pos.x + 1, self.pos.y)
                         ).unwrap();
                     }
@@ -435,7 +453,7 @@ impl InputManager {
                                 Goto(1, self.pos.y),
                                 prompt,
                                 Goto(pl + 1, self.pos.y),
-                                out.to_string(),
+                                out,
                                 Goto(self.pos.x, self.pos.y)
                             ).unwrap();
                         }
@@ -443,49 +461,40 @@ impl InputManager {
                     }
                 }
 
-                // Delete is like backwards backspace.
+                // Delete is like backwards backspace. It erases the character under the cursor.
                 Key::Delete => {
-                    if !out.to_string().is_empty() {
-                        if self.pos.x - pl < (out.to_string().len()) as u16 {
-                            out.delete(&scribe::buffer::Range::new(
-                                scribe::buffer::Position {
-                                    line: 0,
-                                    offset: (self.pos.x - pl) as usize,
-                                },
-                                scribe::buffer::Position {
-                                    line: 0,
-                                    offset: (self.pos.x - pl + 1) as usize,
-                                },
-                            ));
+                    if !out.to_string().is_empty() && self.pos.x - pl < (out.to_string().len()) as u16 {
+                        out.delete(&scribe::buffer::Range::new(
+                            scribe::buffer::Position {
+                                line: 0,
+                                offset: (self.pos.x - pl) as usize,
+                            },
+                            scribe::buffer::Position {
+                                line: 0,
+                                offset: (self.pos.x - pl + 1) as usize,
+                            },
+                        ));
 
-                            write!(
-                                self.terminal,
-                                "{}{}{}{}",
-                                termion::clear::AfterCursor,
-                                Goto(pl + 1, self.pos.y),
-                                out.to_string(),
-                                Goto(self.pos.x + 1, self.pos.y)
-                            ).unwrap();
-                        } //Erase current character
-                        // TODO: Find out why this exists.
-/*                         if self.pos.x - pl > 0 {
-                            self.pos.x - pl;
-                        } */                    }
+                        write!(
+                            self.terminal,
+                            "{}{}{}{}",
+                            termion::clear::AfterCursor,
+                            Goto(pl + 1, self.pos.y),
+                            out,
+                            Goto(self.pos.x + 1, self.pos.y)
+                        ).unwrap();
+                    }
                 }
 
                 // Just enable or disable insert mode.
-                Key::Insert => if self.insert {
-                    self.insert = false;
-                } else {
-                    self.insert = true;
-                },
+                Key::Insert => self.insert = !self.insert,
                 _ => continue,
             }
             self.terminal.flush().unwrap();
         }
 
         write!(self.terminal, "\n{}", termion::clear::CurrentLine).unwrap();
-        if out.to_string().len() > 0 {
+        if !out.to_string().is_empty() {
             for i in out.to_string()
                 .split(|x: char| x.is_whitespace() || !x.is_alphanumeric())
             {
@@ -497,7 +506,7 @@ impl InputManager {
         self.terminal.flush().unwrap();
 
         // Make sure history buffer doesn't contain any empty or whitespace only strings.
-        if self.history.len() > 0 {
+        if !self.history.is_empty() {
             self.history.retain(|t: &String| {
                 !(t.chars()
                     .all(|c: char| c == ' ' || c == '\n' || c == '\t' || c == '\r')
@@ -507,8 +516,8 @@ impl InputManager {
         out.to_string()
     }
 
-    fn handle_alt(&mut self, c: char) {}
-    fn handle_ctrl(&mut self, c: char) {}
+    fn handle_alt(&mut self, _c: char) {}
+    fn handle_ctrl(&mut self, _c: char) {}
 
     fn handle_left(&mut self, prompt_length: &u16) {
         if self.pos.x - prompt_length > 0 {
@@ -524,14 +533,117 @@ impl InputManager {
         }
     }
 
-    fn handle_bkspc(&mut self) {}
-    fn handle_del(&mut self, out: &mut scribe::buffer::GapBuffer, pl: u16) {}
-
     fn handle_tab(&mut self, out: String) {
         // Triggers completion of the entered string.
-        let mut x = self.user_comp_tree.complete(&out.as_bytes());
+        let mut x = self.user_comp_tree.complete(out.as_bytes());
         x.dedup();
         let m = format!("{:?}", x,);
         self.put_line(&m);
     }
 }
+
+// Where the REPL and running programs read from and write to. When stdin is a
+//   terminal we go through InputManager for line editing and coloured output,
+//   otherwise input is piped in and we stick to plain lines.
+pub struct Console {
+    input: Stdin,
+    im: Option<InputManager>,
+    // Lines to read and the lines printed so far, standing in for stdin and stdout in tests.
+    script: Option<(VecDeque<String>, Vec<String>)>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let input = std::io::stdin();
+
+        let im = if termion::is_tty(&input) {
+            Some(InputManager::new())
+        } else {
+            None
+        };
+
+        Console {
+            input,
+            im,
+            script: None,
+        }
+    }
+
+    // A console that never takes over the terminal, even when there is one.
+    #[cfg(test)]
+    pub fn plain() -> Console {
+        Console {
+            input: std::io::stdin(),
+            im: None,
+            script: None,
+        }
+    }
+
+    // A console that reads the given lines and keeps what is printed, for 'printed'.
+    #[cfg(test)]
+    pub fn scripted(lines: &[&str]) -> Console {
+        Console {
+            input: std::io::stdin(),
+            im: None,
+            script: Some((lines.iter().map(|l| l.to_string()).collect(), Vec::new())),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn printed(&self) -> &[String] {
+        self.script.as_ref().map_or(&[], |s| &s.1)
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.im.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(ref mut im) = self.im {
+            im.clear_all();
+        }
+    }
+
+    // Reads a line, showing the prompt first if someone is there to see it.
+    //   Returns None once there is no more input.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        if let Some((ref mut lines, _)) = self.script {
+            return lines.pop_front();
+        }
+
+        match self.im {
+            Some(ref mut im) => Some(im.get_line(&prompt.to_owned(), &mut self.input)),
+            None => {
+                let mut line = String::new();
+                match BufRead::read_line(&mut self.input.lock(), &mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line),
+                }
+            }
+        }
+    }
+
+    pub fn print(&mut self, output: &str) {
+        if let Some((_, ref mut printed)) = self.script {
+            return printed.push(output.to_owned());
+        }
+
+        match self.im {
+            Some(ref mut im) => im.print(output),
+            None => println!("{}", output),
+        }
+    }
+
+    pub fn diagnostic(&mut self, source: &str, diagnostic: &Diagnostic) {
+        match self.im {
+            Some(ref mut im) => im.put_diagnostic(source, diagnostic),
+            None => print_diagnostic(source, diagnostic),
+        }
+    }
+}
+
+fn print_diagnostic(source: &str, diagnostic: &Diagnostic) {
+    for l in diagnostic.render(source) {
+        println!("{}", l);
+    }
+}
@@ -3,10 +3,17 @@ extern crate lazy_static;
 
 extern crate ordered_float;
 
+extern crate bigdecimal;
+
+extern crate num_bigint;
+extern crate num_complex;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+
 #[macro_use]
 extern crate node_t_derive;
 
-#[macro_use]
 extern crate terminal_node_derive;
 
 #[macro_use]
@@ -14,53 +21,52 @@ extern crate encapsulating_node_derive;
 
 extern crate termion;
 
+mod builtins;
+mod decimal;
+mod diagnostic;
 mod io;
 mod parser;
 #[macro_use]
 mod ast;
-//mod interpreter;
+mod interpreter;
+mod value;
+mod word;
 
-use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use io::InputManager;
-//use ast::SymTable;
+use diagnostic::Diagnostic;
+use io::Console;
+use interpreter::Interpreter;
+use value::{DisplayMode, Value};
+use word::{Overflow, Word};
 
 /*
 fn a (x, y, z) { if x == y return z * 2; else return z / 2; } fn b (l, m) { if a(l, m, 2) > 2 { x = 3; y = 16;  m = (l * x) / y; } else m = 2; return m; }
 */
 fn main() {
-    let mut history: Vec<String> = Vec::new();
-    let mut a: String = String::new();
-    let mut parser = parser::Parser::new();
-    let mut d = ast::Program::default();
-
-    //let mut sym_table = SymTable::new();
+    // The interpreter recurses for every nested call and expression, so it runs on a
+    //   thread with a stack big enough for its nesting limits.
+    let child = std::thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(run)
+        .expect("could not start the interpreter thread");
 
-    //*
-    {
-        let mut input = std::io::stdin();
-        //let mut p: io::Point = io::Point::new(1, 1);
-        //let mut im = InputManager::new();
-
-        //im.clear_all();
-
-        while a != String::from("exit") {
-            input.read_line(&mut a).expect("Could not read line from input."); //im.get_line(&"prompt:>".to_owned(), &mut input);
-
-            parser.input(a.clone());
+    // A panic has already been reported by the thread itself.
+    if child.join().is_err() {
+        std::process::exit(101);
+    }
+}
 
-            if a != "exit".to_owned() {
-                d = parser.eval();
-                break;
-            }
-        }
+fn run() {
+    let args: Vec<String> = std::env::args().collect();
 
-        //        im.put_line(&"\r\n".to_owned());
+    // A script file is run as a program. Without one, we start the REPL.
+    if args.len() > 1 {
+        run_file(&args[1]);
+    } else {
+        repl();
     }
 
-    println!("{:#?}", d);
-    //Node::po_(&d);
-
     /*
     {
         let mut res = parser.eval(&"array a = 2 // 4"[..].as_bytes());
@@ -72,3 +78,253 @@ fn main() {
         println!("Answer: {}\n", res.eval());
     } */
 }
+
+// Parses a whole file of function definitions and calls its 'main' function.
+fn run_file(path: &str) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Could not read {}: {}", path, e);
+            return;
+        }
+    };
+
+    let mut console = Console::new();
+    let mut parser = parser::Parser::new();
+    parser.input(source.clone());
+
+    let program = match parser.eval() {
+        Ok(p) => p,
+        Err(errors) => {
+            for e in errors {
+                console.diagnostic(&source, &Diagnostic::from(&e));
+            }
+            return;
+        }
+    };
+
+    #[cfg(feature = "debug")]
+    console.print(&format!("{:#?}", program));
+
+    // The program can print and read through the console with 'write' and 'read'.
+    let mut interpreter = Interpreter::new(console);
+    interpreter.set_source(&source);
+
+    match interpreter.run(&program) {
+        // A main that only writes its output has nothing left to show.
+        Ok(Some(Value::None)) | Ok(None) => {}
+        Ok(Some(val)) => interpreter.console().print(&val.to_string()),
+        Err(e) => interpreter.console().diagnostic(&source, &Diagnostic::from(&e)),
+    }
+}
+
+// REPL settings, changed with commands starting with ':'.
+struct Settings {
+    mode: DisplayMode,
+    radix: u32,
+}
+
+// Runs a REPL command and returns the message to show for it.
+//
+// :exact      shows rationals as fractions, like 1/3. This is the default.
+// :decimal    shows rationals as decimals, like 0.3333333333333333.
+// :precision  computes with decimals of the given number of significant digits
+//               instead of floats, as in ':precision 50'. ':precision off' goes
+//               back to floats, and ':precision' on its own shows the current setting.
+// :rounding   sets how decimals are rounded: up, down, ceiling, floor, half-up,
+//               half-down or half-even, which is the default.
+// :base       shows integers in base 2, 8, 10 or 16, as in ':base 16'.
+// :input      reads integers typed without a prefix in base 2, 8, 10 or 16, as in
+//               ':input 16'. Names still win, so in base 16 'ff' is a name and '0ff' is 255.
+// :word       turns on programmer mode, where integers are fixed size words like
+//               u8 or i32, from 8 up to 128 bits. ':word off' turns it off again.
+// :overflow   sets what happens when a result does not fit in the word: 'wrap'
+//               keeps the low bits, which is the default, and 'check' raises an error.
+fn command(cmd: &str, settings: &mut Settings, parser: &mut parser::Parser) -> String {
+    let words: Vec<&str> = cmd.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["exact"] => {
+            settings.mode = DisplayMode::Exact;
+            "Showing exact results".to_owned()
+        }
+        ["decimal"] => {
+            settings.mode = DisplayMode::Decimal;
+            "Showing decimal results".to_owned()
+        }
+        ["precision"] => match decimal::precision() {
+            Some(d) => format!("Computing with {} significant digits", d),
+            None => "Computing with floats".to_owned(),
+        },
+        ["precision", "off"] => {
+            decimal::set_precision(None);
+            "Computing with floats".to_owned()
+        }
+        ["precision", digits] => match digits.parse::<u64>() {
+            Ok(d) if d > 0 => {
+                decimal::set_precision(Some(d));
+                format!("Computing with {} significant digits", d)
+            }
+            _ => format!("Precision must be a positive number of digits, not {}", digits),
+        },
+        ["base", radix] => match radix.parse::<u32>() {
+            Ok(r) if r == 2 || r == 8 || r == 10 || r == 16 => {
+                settings.radix = r;
+                format!("Showing integers in base {}", r)
+            }
+            _ => format!("Base must be 2, 8, 10 or 16, not {}", radix),
+        },
+        ["input"] => format!("Reading integers in base {}", parser.get_base()),
+        ["input", radix] => match radix.parse::<u32>() {
+            Ok(r) if r == 2 || r == 8 || r == 10 || r == 16 => {
+                parser.base(r);
+                format!("Reading integers in base {}", r)
+            }
+            _ => format!("Base must be 2, 8, 10 or 16, not {}", radix),
+        },
+        ["word"] => match word::word() {
+            Some(w) => format!("Integers are {}", w.name()),
+            None => "Integers are unbounded".to_owned(),
+        },
+        ["word", "off"] => {
+            word::set_word(None);
+            "Integers are unbounded".to_owned()
+        }
+        ["word", name] => match Word::from_name(name) {
+            Some(w) => {
+                word::set_word(Some(w));
+                format!("Integers are {}", w.name())
+            }
+            None => format!("Unknown word size {}, expected one of u8, i8, u16, i16 and so on up to i128", name),
+        },
+        ["overflow", "wrap"] => {
+            word::set_overflow(Overflow::Wrap);
+            "Overflowing results wrap around".to_owned()
+        }
+        ["overflow", "check"] => {
+            word::set_overflow(Overflow::Check);
+            "Overflowing results are errors".to_owned()
+        }
+        ["rounding"] => format!("Rounding {}", decimal::rounding_name(decimal::rounding())),
+        ["rounding", mode] => match decimal::rounding_from_name(mode) {
+            Some(m) => {
+                decimal::set_rounding(m);
+                format!("Rounding {}", mode)
+            }
+            None => format!(
+                "Unknown rounding mode {}, expected up, down, ceiling, floor, half-up, half-down or half-even",
+                mode
+            ),
+        },
+        _ => format!("Unknown command :{}", cmd),
+    }
+}
+
+// The read-eval-print loop. Each line can hold expressions, assignments and
+//   function declarations; variables and functions are kept between lines.
+//   The console belongs to the interpreter, since 'read' and 'write' use it too.
+//   State variables from the last session are loaded first and saved on exit.
+fn repl() {
+    let mut parser = parser::Parser::new();
+    let mut interpreter = Interpreter::new(Console::new());
+    let mut settings = Settings {
+        mode: DisplayMode::Exact,
+        radix: 10,
+    };
+
+    interpreter.console().clear();
+    load_state(&mut parser, &mut interpreter);
+
+    while let Some(line) = interpreter.console().read_line("rc:> ") {
+        let line = line.trim().to_owned();
+
+        if line == "exit" {
+            break;
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(cmd) = line.strip_prefix(':') {
+            let msg = command(cmd, &mut settings, &mut parser);
+            interpreter.console().print(&msg);
+            continue;
+        }
+
+        parser.input(line.clone());
+        interpreter.set_source(&line);
+
+        match parser.parse_repl_line() {
+            Ok(stmts) => {
+                #[cfg(feature = "debug")]
+                interpreter.console().print(&format!("{:#?}", stmts));
+
+                match interpreter.exec_line(&stmts) {
+                    Ok(Some(val)) => interpreter.console().print(&val.display(settings.mode, settings.radix)),
+                    Ok(None) => {}
+                    Err(e) => interpreter.console().diagnostic(&line, &Diagnostic::from(&e)),
+                }
+            }
+            Err(errors) => for e in errors {
+                interpreter.console().diagnostic(&line, &Diagnostic::from(&e));
+            },
+        }
+    }
+
+    save_state(&mut interpreter);
+}
+
+// Where state variables are kept between sessions: the file named by the
+//   RUSTCALC_STATE environment variable, or '.rustcalc_state' in the home directory.
+fn state_path() -> Option<PathBuf> {
+    match std::env::var_os("RUSTCALC_STATE") {
+        Some(p) => Some(PathBuf::from(p)),
+        None => std::env::var_os("HOME").map(|h| Path::new(&h).join(".rustcalc_state")),
+    }
+}
+
+// Runs the state file from the last session, which brings back its state variables.
+//   A line that fails is reported and skipped, so one bad value does not lose the rest.
+fn load_state(parser: &mut parser::Parser, interpreter: &mut Interpreter) {
+    let source = match state_path().and_then(|p| std::fs::read_to_string(p).ok()) {
+        Some(s) => s,
+        None => return,
+    };
+
+    for line in source.lines() {
+        parser.input(line.to_owned());
+        interpreter.set_source(line);
+
+        let res = match parser.parse_repl_line() {
+            Ok(stmts) => interpreter.exec_line(&stmts).map_err(|e| vec![Diagnostic::from(&e)]),
+            Err(errors) => Err(errors.iter().map(Diagnostic::from).collect()),
+        };
+
+        if let Err(diagnostics) = res {
+            for d in diagnostics {
+                interpreter.console().diagnostic(line, &d);
+            }
+        }
+    }
+}
+
+fn save_state(interpreter: &mut Interpreter) {
+    let path = match state_path() {
+        Some(p) => p,
+        None => return,
+    };
+
+    let state = interpreter.save_state();
+
+    // Don't leave a file behind for sessions that never used 'state'.
+    if state.is_empty() && !path.exists() {
+        return;
+    }
+
+    if let Err(e) = std::fs::write(&path, state) {
+        interpreter
+            .console()
+            .print(&format!("Could not save state to {}: {}", path.display(), e));
+    }
+}
@@ -1,14 +1,18 @@
 use ast::{
     ArgDeclList, 
     ArgList, 
+    ArrayLiteral, 
     CondBlock, 
     Expr, 
     Factor, 
     FnCall, 
     Function, 
+    MapLiteral, 
+    Param, 
     Program, 
     Scope, 
-    Statement
+    Statement, 
+    TupleLiteral
 };
 
 use std::fmt;
@@ -16,15 +20,18 @@ use std::fmt;
 // For distinguishing enum types.
 use std::mem::discriminant;
 
-// Operator overloads.
-use std::ops::*;
-
 use std::collections::HashMap;
 use std::f64;
+use std::ops::Range;
 
 use ordered_float::OrderedFloat;
 
-pub type SymTable = HashMap<String, Token>;
+use bigdecimal::BigDecimal;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+pub type SymTable<T = Token> = HashMap<String, T>;
 
 
 macro_rules! boxe {
@@ -39,18 +46,49 @@ lazy_static! {
 
         let m = [
             "state", "if", "else", "elif", "return", "write", "read", "for", "in", "array", "fn",
+            "while", "break", "continue",
         ];
 
-        for i in 0..m.len() {
-            d.insert(m[i].to_owned(), Token::KeyWord(m[i].to_owned()));
+        for k in m.iter() {
+            d.insert(k.to_string(), Token::KeyWord(k.to_string()));
         }
 
         d
     };
 }
 
+// The precedence of comparisons. '!' applies to a whole comparison, so its operand
+//   is parsed at this level.
+const COMPARISON_PRECEDENCE: u8 = 3;
+
+// How deeply expressions and statements can nest, as in '((((1))))' or '- - - 1'. The
+//   parser recurses for each level, so input nested deeper is an error rather than a
+//   stack overflow.
+const MAX_NESTING: usize = 256;
+
+// How tightly each binary operator binds; higher binds tighter. This follows python,
+//   so the bitwise operators sit between comparisons and arithmetic.
+fn binary_precedence(op: &Op) -> Option<u8> {
+    match op {
+        Op::Or_ => Some(1),
+        Op::And => Some(2),
+        Op::Eq_ | Op::Neq | Op::Lt_ | Op::Gt_ | Op::Leq | Op::Geq => Some(COMPARISON_PRECEDENCE),
+        Op::BitOr => Some(4),
+        Op::BitXor => Some(5),
+        Op::BitAnd => Some(6),
+        Op::Lls | Op::Ars | Op::Lrs => Some(7),
+        Op::Add | Op::Sub => Some(8),
+        Op::Mul | Op::Div | Op::IntDiv | Op::Mod => Some(9),
+        _ => None,
+    }
+}
+
+fn is_tuple(f: &Factor) -> bool {
+    matches!(f, Factor::Tuple(_))
+}
+
 // Enum of operator IDs recognised by the parser.
-#[derive(Clone, Debug, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub enum Op {
     Add,
@@ -75,22 +113,89 @@ pub enum Op {
     BitOr,
     BitNot,
     BitXor,
-    LLS,
-    LRS,
-    ARS,
+    Lls,
+    Lrs,
+    Ars,
     Assign,
+    // '=>', which separates the parameters of a lambda from its body.
+    Arrow,
+    // An operator followed by '=', as in '+=', holding the operator.
+    CompoundAssign(Box<Op>),
     Comma,
     LParens,
     RParens,
+    LBracket,
+    RBracket,
+    Colon,
     BlockStart,
     BlockEnd,
     LineEnd,
-    Any,
 }
 
-impl PartialEq for Op {
-    fn eq(&self, other: &Op) -> bool {
-        discriminant(self) == discriminant(other)
+impl Op {
+    // Whether the operator has a compound assignment form, like '+='.
+    pub fn has_compound(&self) -> bool {
+        matches!(
+            self,
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::IntDiv | Op::Mod | Op::Pow
+          | Op::BitAnd | Op::BitOr | Op::BitXor | Op::Lls | Op::Lrs | Op::Ars
+        )
+    }
+
+    // The source text of the operator, for error messages.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Op::Add | Op::Pos => "+",
+            Op::Sub | Op::Neg => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::IntDiv => "//",
+            Op::Mod => "%",
+            Op::Pow => "**",
+            Op::Eq_ => "==",
+            Op::Gt_ => ">",
+            Op::Lt_ => "<",
+            Op::Geq => ">=",
+            Op::Leq => "<=",
+            Op::Neq => "!=",
+            Op::And => "&&",
+            Op::Or_ => "||",
+            Op::Not => "!",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitNot => "~",
+            Op::BitXor => "^",
+            Op::Lls => "<<",
+            Op::Lrs => ">>>",
+            Op::Ars => ">>",
+            Op::Assign => "=",
+            Op::Arrow => "=>",
+            Op::CompoundAssign(ref op) => match **op {
+                Op::Add => "+=",
+                Op::Sub => "-=",
+                Op::Mul => "*=",
+                Op::Div => "/=",
+                Op::IntDiv => "//=",
+                Op::Mod => "%=",
+                Op::Pow => "**=",
+                Op::BitAnd => "&=",
+                Op::BitOr => "|=",
+                Op::BitXor => "^=",
+                Op::Lls => "<<=",
+                Op::Lrs => ">>>=",
+                Op::Ars => ">>=",
+                _ => "=",
+            },
+            Op::Comma => ",",
+            Op::LParens => "(",
+            Op::RParens => ")",
+            Op::LBracket => "[",
+            Op::RBracket => "]",
+            Op::Colon => ":",
+            Op::BlockStart => "{",
+            Op::BlockEnd => "}",
+            Op::LineEnd => ";",
+        }
     }
 }
 
@@ -98,12 +203,17 @@ impl PartialEq for Op {
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub enum Token {
-    Number(f64),
+    Int(BigInt),
+    // Fractional literals keep every digit that was typed, so they can be used
+    //   as decimals when a precision is set. Otherwise they become floats.
+    Number(BigDecimal),
+    // An imaginary literal such as '2.5j'; the value is the coefficient of i.
+    Imag(f64),
+    Str(String),
     Bool(bool),
     Operator(Op),
     Var(String),
     Other(char),
-    Good,
     Bad,
     KeyWord(String),
     None,
@@ -123,20 +233,52 @@ impl Token {
             _ => None
         }
     }
+
+    // The kind of token, for listing what the parser expected in error messages.
+    pub fn kind_name(&self) -> String {
+        match self {
+            Token::Int(_) | Token::Number(_) | Token::Imag(_) => "number".to_owned(),
+            Token::Bool(_) => "boolean".to_owned(),
+            Token::Str(_) => "string".to_owned(),
+            Token::Var(_) => "identifier".to_owned(),
+            Token::Operator(Op::LineEnd) => "';'".to_owned(),
+            _ => self.describe(),
+        }
+    }
+
+    // A human readable description of the token, including its value, for error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Int(x) => format!("number {}", x),
+            Token::Number(x) => format!("number {}", x),
+            Token::Imag(x) => format!("number {}j", x),
+            Token::Bool(x) => format!("boolean {}", x),
+            Token::Str(x) => format!("string {:?}", x),
+            Token::Operator(Op::LineEnd) => "end of statement".to_owned(),
+            Token::Operator(o) => format!("'{}'", o.symbol()),
+            Token::Var(x) => format!("identifier '{}'", x),
+            Token::KeyWord(x) => format!("keyword '{}'", x),
+            Token::Other(x) => format!("'{}'", x),
+            Token::Bad => format!("{:?}", self),
+            Token::None => "end of input".to_owned(),
+        }
+    }
 }
 
 impl PartialEq for Token {
     fn eq(&self, other: &Token) -> bool {
         match (self, other) {
-            (&Token::Number(ref x), &Token::Number(ref y)) => OrderedFloat::from(*x) == OrderedFloat::from(*y),
-            (&Token::Bool(x), &Token::Bool(y)) => x == y,
-            (&Token::Operator(Op::Any), &Token::Operator(_)) => true,
-            (&Token::Operator(ref x), &Token::Operator(ref y)) => x == y,
-            (&Token::Var(ref x), &Token::Var(ref y)) => x == y,
-            (&Token::Other(_), &Token::Other(_)) => false,
-            (&Token::KeyWord(ref x), &Token::KeyWord(ref y)) => x == y,
-            (&Token::None, _) => false,
-            _ => discriminant(&self) == discriminant(&other),
+            (Token::Int(x), Token::Int(y)) => x == y,
+            (Token::Number(x), Token::Number(y)) => x == y,
+            (Token::Imag(x), Token::Imag(y)) => OrderedFloat::from(*x) == OrderedFloat::from(*y),
+            (Token::Bool(x), Token::Bool(y)) => x == y,
+            (Token::Str(x), Token::Str(y)) => x == y,
+            (Token::Operator(x), Token::Operator(y)) => x == y,
+            (Token::Var(x), Token::Var(y)) => x == y,
+            (Token::Other(_), Token::Other(_)) => false,
+            (Token::KeyWord(x), Token::KeyWord(y)) => x == y,
+            (Token::None, _) => false,
+            _ => discriminant(self) == discriminant(other),
         }
     }
 }
@@ -174,112 +316,108 @@ impl Default for TokStruct {
     }
 }
 
-impl Add for Token {
-    type Output = Token;
-
-    fn add(self, other: Token) -> Token {
-        match (self, other) {
-            (Token::Number(ref x), Token::Number(ref y)) => Token::Number(x + y),
-            (Token::Var(ref x), Token::Var(ref y)) => Token::None,
-            _ => Token::None,
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Int(x) => write!(f, "{}", x),
+            Token::Number(x) => write!(f, "{}", x),
+            Token::Imag(x) => write!(f, "{}j", x),
+            Token::Bool(x) => write!(f, "{}", x),
+            Token::Str(x) => write!(f, "{:?}", x),
+            Token::Var(x) => write!(f, "{}", x),
+            Token::Operator(x) => write!(f, "{:?}", x),
+            _ => write!(f, "{:?}", self),
         }
     }
 }
 
-impl Sub for Token {
-    type Output = Token;
-
-    fn sub(self, other: Token) -> Token {
-        match (self, other) {
-            (Token::Number(ref x), Token::Number(ref y)) => Token::Number(x - y),
-            // TODO: Implement code for variables.
-            (Token::Var(ref x), Token::Var(ref y)) => Token::None,
-            _ => Token::None,
-        }
-    }
+// The different ways lexing or parsing can fail.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    // A run of digits that does not form a valid number, like '1.2.3'.
+    InvalidNumber,
+    // A character the lexer has no token for.
+    UnknownSymbol,
+    // The parser found a token it did not expect at this point.
+    UnexpectedToken,
+    // A string literal with no closing quote on its line.
+    UnterminatedString,
+    // A backslash in a string literal followed by something that is not an escape.
+    InvalidEscape,
+    // A 'break' or 'continue' that is not inside a loop.
+    OutsideLoop,
+    // Expressions or blocks nested more deeply than MAX_NESTING.
+    TooDeep,
+    // A '/*' comment with no closing '*/'.
+    UnterminatedComment,
+    // A positional argument after a keyword argument, as in 'f(a=1, 2)'.
+    PositionalAfterKeyword,
 }
 
-impl Mul for Token {
-    type Output = Token;
-
-    fn mul(self, other: Token) -> Token {
-        match (self, other) {
-            (Token::Number(ref x), Token::Number(ref y)) => Token::Number(x * y),
-            (Token::Var(ref x), Token::Var(ref y)) => Token::None,
-            _ => Token::None,
-        }
-    }
+// A recoverable lexer or parser error. The span is a half-open range of byte
+//   offsets into the input, starting at the position of the offending token.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Range<usize>,
+    pub expected: Vec<Token>,
+    pub found: Token,
 }
 
-impl Div for Token {
-    type Output = Token;
-
-    fn div(self, other: Token) -> Token {
-        match (self, other) {
-            (Token::Number(ref x), Token::Number(ref y)) => if *y != 0f64 {
-                Token::Number(x / y)
-            } else {
-                Token::Bad
-            },
-            (Token::Var(ref x), Token::Var(ref y)) => Token::None,
-            _ => Token::None,
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Range<usize>, expected: Vec<Token>, found: Token) -> ParseError {
+        ParseError {
+            kind,
+            span,
+            expected,
+            found,
         }
     }
-}
-
-impl Rem for Token {
-    type Output = Token;
 
-    fn rem(self, other: Token) -> Token {
-        match (self, other) {
-            (Token::Number(ref x), Token::Number(ref y)) => if *y != 0f64 {
-                Token::Number((*x as i64 % *y as i64) as f64)
+    // A short description of the error, without any position information.
+    pub fn message(&self) -> String {
+        match self.kind {
+            ParseErrorKind::InvalidNumber => "malformed number".to_owned(),
+            ParseErrorKind::UnknownSymbol => format!("unknown symbol {}", self.found.describe()),
+            ParseErrorKind::UnterminatedString => "unterminated string".to_owned(),
+            ParseErrorKind::InvalidEscape => "unknown escape sequence in string".to_owned(),
+            ParseErrorKind::OutsideLoop => format!("{} outside a loop", self.found.describe()),
+            ParseErrorKind::TooDeep => "too many levels of nesting".to_owned(),
+            ParseErrorKind::UnterminatedComment => "unterminated comment".to_owned(),
+            ParseErrorKind::PositionalAfterKeyword => "positional argument follows keyword argument".to_owned(),
+            ParseErrorKind::UnexpectedToken => if self.expected.is_empty() {
+                format!("unexpected {}", self.found.describe())
             } else {
-                Token::Bad
+                format!(
+                    "expected {}, found {}",
+                    self.expected
+                        .iter()
+                        .map(|t| t.kind_name())
+                        .collect::<Vec<String>>()
+                        .join(" or "),
+                    self.found.describe()
+                )
             },
-            (Token::Var(ref x), Token::Var(ref y)) => Token::None,
-            _ => Token::None,
         }
     }
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &Token::Number(ref x) => write!(f, "{}", x),
-            &Token::Var(ref x) => write!(f, "{}", x),
-            &Token::Operator(ref x) => write!(f, "{:?}", x),
-            _ => write!(f, "{:?}", self),
-        }
+        write!(f, "Parse error at position {}: {}", self.span.start, self.message())
     }
 }
 
-// Conversions from the Token type to long & double.
-
-impl From<Token> for f64 {
-    fn from(tok: Token) -> f64 {
-        match tok {
-            Token::Number(x) => x,
-            _ => 0f64,
-        }
-    }
-}
+pub type PResult<T> = Result<T, ParseError>;
 
-impl From<Token> for i64 {
-    fn from(tok: Token) -> i64 {
-        match tok {
-            Token::Number(x) => x as i64,
-            _ => 0i64,
-        }
-    }
-}
 
 // Lexer implementation.
 pub struct Lexer {
     // The current position for the given input.
     pos: usize,
 
-    // Base for parsing numbers.
+    // Base for parsing integers without a prefix. Letters are only read as digits
+    //   after a number has started, so in base 16 'ff' is still a name and '0ff' is 255.
     base: u32,
 
     // THe current token + metadata.
@@ -329,22 +467,39 @@ impl Lexer {
         self
     }
 
-    fn reset(&mut self) {
+    fn reset(&mut self) -> PResult<()> {
         self.pos = 0;
-        self.curr = self.get_next();
+        self.curr = TokStruct::new(Token::None, 0);
+        self.curr = self.get_next()?;
+        Ok(())
     }
 
-    // Peek forward by one character.
+    // Peek forward by one character. Returns '\0' past the end of the input.
     fn peek(&self) -> char {
-        self.input[self.pos + 1] as char
+        if self.pos + 1 < self.input.len() {
+            self.input[self.pos + 1] as char
+        } else {
+            '\0'
+        }
+    }
+
+    fn peek_token(&mut self) -> PResult<TokStruct> {
+        self.peek_nth_token(1)
     }
 
-    fn peek_token(&mut self) -> TokStruct {
+    // Looks n tokens ahead without moving, so peek_nth_token(1) is the next token.
+    fn peek_nth_token(&mut self, n: usize) -> PResult<TokStruct> {
         let cc = self.get_curr();
         let cp = self.get_pos();
 
-        // Get next token. This will advance the lexer forward by one token.
-        let m = self.get_next();
+        // Get the nth token. This will advance the lexer forward by n tokens.
+        let mut m = self.get_next();
+        for _ in 1..n {
+            if m.is_err() {
+                break;
+            }
+            m = self.get_next();
+        }
 
         // We need to reset thr lexer's position so it doesn't miss a token.
         self.set_pos(cp);
@@ -353,42 +508,200 @@ impl Lexer {
         m
     }
 
-    // Peek backward to get the previous non-whitespace character.
-    fn peek_back(&self) -> char {
-        let mut pb = self.pos - 1;
-        if pb > 0 {
-            while pb > 0 && self.input[pb] as char == ' ' {
-                pb -= 1;
-            }
-            self.input[pb] as char
-        } else {
-            self.input[pb] as char
-        }
+    // The span of the current token; it runs from the token's start up to the lexer's position.
+    pub fn curr_span(&self) -> Range<usize> {
+        let start = self.curr.get_pos();
+        start..::std::cmp::max(self.pos, start + 1)
     }
 
-    // Parses numbers. Returns a TokStruct with a Token::Number and its position in the string.
-    fn get_number(&mut self) -> TokStruct {
+    // Parses numbers. Returns a TokStruct with a Token::Int if the number is a
+    //   plain run of digits, or a Token::Number if it has a fraction or an exponent.
+    //   A trailing 'i' or 'j' makes it an imaginary Token::Imag, as in '3j' or '2.5i'.
+    //
+    //   Integers are read in the lexer's base, unless they start with a '0x', '0o' or
+    //   '0b' prefix for hex, octal or binary. Digits can be grouped with underscores,
+    //   as in '1_000_000' or '0xFFFF_0000'.
+    fn get_number(&mut self) -> PResult<TokStruct> {
+        let start = self.pos;
         let mut num: String = "".into();
+        let mut is_float = false;
+        let mut base = self.base;
+
+        // A prefix letter that is also a digit, like 'b' in base 16, is read as a digit.
+        if self.input[self.pos] as char == '0' && !self.peek().is_digit(self.base) {
+            let radix = match self.peek() {
+                'x' | 'X' => Some(16),
+                'o' | 'O' => Some(8),
+                'b' | 'B' => Some(2),
+                _ => None,
+            };
+
+            if let Some(r) = radix {
+                base = r;
+                self.pos += 2;
+            }
+        }
 
         // Manual bounds checking since iterators aren't as versatile as I'd want them to be.
-        while self.pos < self.input.len()
-            // Make sure current character is a digit or a '.'
-            && ((self.input[self.pos] as char).is_digit(self.base)
-                || self.input[self.pos] as char == '.')
-        {
-            num.push(self.input[self.pos] as char);
+        while self.pos < self.input.len() {
+            let ch = self.input[self.pos] as char;
+
+            // Make sure current character is a digit, a '.' in a decimal number, or an
+            //   underscore between two digits.
+            if ch.is_digit(base) || (ch == '.' && base == 10) {
+                is_float |= ch == '.';
+                num.push(ch);
+            } else if !(ch == '_'
+                && num.chars().last().is_some_and(|c| c.is_digit(base))
+                && self.peek().is_digit(base))
+            {
+                break;
+            }
+
+            self.pos += 1;
+        }
+
+        // An exponent, as in '1e-9'. It is only taken if digits follow, so '2e' is still '2' times 'e'.
+        if base == 10 && self.pos < self.input.len() && (self.input[self.pos] as char == 'e' || self.input[self.pos] as char == 'E') {
+            let mut end = self.pos + 1;
+
+            if end < self.input.len() && (self.input[end] as char == '+' || self.input[end] as char == '-') {
+                end += 1;
+            }
+
+            if end < self.input.len() && (self.input[end] as char).is_ascii_digit() {
+                while end < self.input.len() && (self.input[end] as char).is_ascii_digit() {
+                    end += 1;
+                }
+
+                num.push_str(&String::from_utf8_lossy(&self.input[self.pos..end]));
+                self.pos = end;
+                is_float = true;
+            }
+        }
+
+        // The imaginary suffix. It must end the word, so '2if' or '3jx' are not imaginary numbers.
+        let is_imag = self.pos < self.input.len()
+            && (self.input[self.pos] as char == 'i' || self.input[self.pos] as char == 'j')
+            && !(self.pos + 1 < self.input.len()
+                && ((self.input[self.pos + 1] as char).is_alphanumeric() || self.input[self.pos + 1] as char == '_'));
+
+        if is_imag {
             self.pos += 1;
         }
 
+        let tok = if is_imag {
+            if is_float {
+                num.parse().ok()
+            } else {
+                BigInt::parse_bytes(num.as_bytes(), base).and_then(|x| x.to_f64())
+            }.map(Token::Imag)
+        } else if is_float {
+            num.parse::<BigDecimal>().ok().map(Token::Number)
+        } else {
+            BigInt::parse_bytes(num.as_bytes(), base).map(Token::Int)
+        };
+
         // If we have a valid number at the end of this, we can return.
-        match num.parse() {
-            Ok(val) => TokStruct::new(Token::Number(val), self.pos - num.len()),
-            Err(_) => panic!(
-                "Lexer error at position {}! {} is not a number!",
-                self.pos - num.len(),
-                num
-            ),
+        match tok {
+            Some(t) => Ok(TokStruct::new(t, start)),
+            None => Err(ParseError::new(
+                ParseErrorKind::InvalidNumber,
+                start..self.pos,
+                vec![Token::Number(BigDecimal::default())],
+                Token::Bad,
+            )),
+        }
+    }
+
+    // Parses string literals, which can be quoted with either '"' or '\''. The usual
+    //   escapes work: '\n', '\t', '\r', '\0', '\\', '\'' and '\"', and '\u{...}' gives
+    //   any unicode character by its hex code. A string has to end on the line it starts on.
+    fn get_string(&mut self) -> PResult<TokStruct> {
+        let start = self.pos;
+        let quote = self.input[self.pos];
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut bad_escape = None;
+
+        self.pos += 1;
+
+        loop {
+            if self.pos >= self.input.len() || self.input[self.pos] == b'\n' {
+                return Err(ParseError::new(
+                    ParseErrorKind::UnterminatedString,
+                    start..self.pos,
+                    vec![],
+                    Token::Bad,
+                ));
+            }
+
+            let b = self.input[self.pos];
+            self.pos += 1;
+
+            if b == quote {
+                break;
+            }
+
+            if b != b'\\' {
+                bytes.push(b);
+                continue;
+            }
+
+            // An escape sequence. A bad one is reported once the whole string has been
+            //   read, so the rest of the string is not lexed as code.
+            let esc = match self.input.get(self.pos).map(|&c| c as char) {
+                Some('n') => Some('\n'),
+                Some('t') => Some('\t'),
+                Some('r') => Some('\r'),
+                Some('0') => Some('\0'),
+                Some('\\') => Some('\\'),
+                Some('\'') => Some('\''),
+                Some('"') => Some('"'),
+                Some('u') => self.get_unicode_escape(),
+                _ => None,
+            };
+
+            // The character after a bad backslash is read as a normal character,
+            //   in case it is the closing quote or the end of the line.
+            match esc {
+                Some(c) => {
+                    let mut buf = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    self.pos += 1;
+                }
+                None => if bad_escape.is_none() {
+                    bad_escape = Some(self.pos - 1);
+                },
+            }
+        }
+
+        match bad_escape {
+            Some(p) => Err(ParseError::new(
+                ParseErrorKind::InvalidEscape,
+                p..p + 2,
+                vec![],
+                Token::Bad,
+            )),
+            None => Ok(TokStruct::new(
+                Token::Str(String::from_utf8_lossy(&bytes).into_owned()),
+                start,
+            )),
+        }
+    }
+
+    // Reads the '{...}' part of a '\u{...}' escape, with the lexer on the 'u'. On success
+    //   the lexer is left on the closing brace.
+    fn get_unicode_escape(&mut self) -> Option<char> {
+        if self.input.get(self.pos + 1) != Some(&b'{') {
+            return None;
         }
+
+        let close = self.input[self.pos..].iter().position(|&c| c == b'}')? + self.pos;
+        let hex = ::std::str::from_utf8(&self.input[self.pos + 2..close]).ok()?;
+        let c = u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32)?;
+
+        self.pos = close;
+        Some(c)
     }
 
     // Parses variables. A variable identifier is composed of digits,
@@ -408,201 +721,302 @@ impl Lexer {
         TokStruct::new(Token::Var(n.clone()), self.pos - n.len())
     }
 
-    // Parses boolean values. A boolean value is either 'true' or 'false'.
-    fn get_bool(&mut self) -> TokStruct {
-        // We go through get_var() to grab strings from input.
-        let d = self.get_var();
-
-        // We need to use a match here to destructure the underlying Token
-        //   even though we know it's going to be a Token::Var because that's
-        //   the only way to get at the underlying data of an enum variable.
-        match d.get_val() {
-            Token::Var(x) => match x.as_str() {
-                "true" => TokStruct::new(Token::Bool(true), self.pos - 4),
-                "false" => TokStruct::new(Token::Bool(false), self.pos - 5),
-                _ => panic!(
-                    "Expected boolean value at position {}, got {}!\n",
-                    d.get_pos(),
-                    d.get_val()
-                ),
-            },
-            _ => panic!("Expected boolean value at position {}!\n", d.get_pos()),
-        }
-    }
-
     // The main interface of the lexer. It advances token by token, and outputs a single token for each non-whitespace character it reads from input.
-    pub fn get_next(&mut self) -> TokStruct {
+    pub fn get_next(&mut self) -> PResult<TokStruct> {
+        self.skip_blank()?;
+
         // Bounds check & initialisation of the current token.
-        if !(self.pos < self.input.len()) {
+        if self.pos >= self.input.len() {
             self.curr = TokStruct::new(Token::None, self.pos);
-            return self.curr.clone();
+            return Ok(self.curr.clone());
         }
 
-        let mut ch = self.input[self.pos] as char;
+        let ch = self.input[self.pos] as char;
+        let start = self.pos;
 
-        // This block skips any whitespace.
-        if ch == ' ' {
-            while ch == ' ' {
-                if self.pos < (self.input.len() - 1) {
-                    self.pos += 1;
-                } else {
-                    return TokStruct::new(Token::None, 0);
+        // The real parsing gets done at these if-else statements.
+
+        //  Number. Only a decimal digit starts one, so names always win over digits in
+        //    bases past 10, and fractions like '.5' are only read in base 10.
+        if ch.is_ascii_digit() || (ch == '.' && self.base == 10) {
+            match self.get_number() {
+                Ok(t) => self.curr = t,
+                Err(e) => {
+                    // The bad number becomes the current token so the parser can skip past it.
+                    self.curr = TokStruct::new(Token::Bad, e.span.start);
+                    return Err(e);
                 }
-                ch = self.input[self.pos] as char;
             }
-        }
 
-        // The real parsing gets done at these if-else statements.
-
-        //  Number
-        if ch.is_digit(self.base) || ch == '.' {
-            self.curr = self.get_number();
+        // String
+        } else if ch == '"' || ch == '\'' {
+            match self.get_string() {
+                Ok(t) => self.curr = t,
+                Err(e) => {
+                    self.curr = TokStruct::new(Token::Bad, e.span.start);
+                    return Err(e);
+                }
+            }
 
         // Identifier
         } else if ch == '_' || ch.is_alphabetic() {
             self.curr = self.get_var();
 
             match self.curr.get_val() {
-                Token::Var(ref x) => if KEYWORD_TABLE.contains_key(x) {
+                Token::Var(ref x) => if x == "true" || x == "false" {
+                    self.curr.val = Token::Bool(x == "true");
+                } else if KEYWORD_TABLE.contains_key(x) {
                     self.curr.val = KEYWORD_TABLE.get(x).unwrap().clone();
                 },
                 _ => panic!("This should never happen."),
             }
 
         // Symbol
-        // TODO: Add support for shorthand operators such as '+='
         } else {
-            self.curr = TokStruct::new(
-                match ch {
-                    // If the previous token is a number or identifier, we know it's a binary operator.
-                    // If the previous token is an operator, we can consider this token to be a unary operator.
-                    '+' => match self.curr.get_val() {
-                        Token::Number(_) | Token::Var(_) | Token::Operator(Op::RParens) => {
-                            Token::Operator(Op::Add)
-                        }
-                        _ => Token::Operator(Op::Pos),
-                    },
+            let tok = match ch {
+                // If the previous token is a number or identifier, we know it's a binary operator.
+                // If the previous token is an operator, we can consider this token to be a unary operator.
+                '+' => match self.curr.get_val() {
+                    Token::Int(_) | Token::Number(_) | Token::Imag(_) | Token::Str(_) | Token::Bool(_) | Token::Var(_) | Token::Operator(Op::RParens) | Token::Operator(Op::RBracket) => {
+                        Token::Operator(Op::Add)
+                    }
+                    _ => Token::Operator(Op::Pos),
+                },
 
-                    // Same.
-                    '-' => match self.curr.get_val() {
-                        Token::Number(_) | Token::Var(_) | Token::Operator(Op::RParens) => {
-                            Token::Operator(Op::Sub)
-                        }
-                        _ => Token::Operator(Op::Neg),
-                    },
+                // Same.
+                '-' => match self.curr.get_val() {
+                    Token::Int(_) | Token::Number(_) | Token::Imag(_) | Token::Str(_) | Token::Bool(_) | Token::Var(_) | Token::Operator(Op::RParens) | Token::Operator(Op::RBracket) => {
+                        Token::Operator(Op::Sub)
+                    }
+                    _ => Token::Operator(Op::Neg),
+                },
 
-                    // The peek forward method is used here to differentiate Op::Mul and Op::Pow.
-                    '*' => if self.peek() == '*' {
-                        self.pos += 1;
-                        Token::Operator(Op::Pow)
-                    } else {
-                        Token::Operator(Op::Mul)
-                    },
-                    '/' => if self.peek() == '/' {
-                        self.pos += 1;
-                        Token::Operator(Op::IntDiv)
-                    } else {
-                        Token::Operator(Op::Div)
-                    },
-                    '^' => Token::Operator(Op::BitXor),
-                    '&' => if self.peek() == '&' {
-                        self.pos += 1;
-                        Token::Operator(Op::And)
-                    } else {
-                        Token::Operator(Op::BitAnd)
-                    },
+                // The peek forward method is used here to differentiate Op::Mul and Op::Pow.
+                '*' => if self.peek() == '*' {
+                    self.pos += 1;
+                    Token::Operator(Op::Pow)
+                } else {
+                    Token::Operator(Op::Mul)
+                },
+                '/' => if self.peek() == '/' {
+                    self.pos += 1;
+                    Token::Operator(Op::IntDiv)
+                } else {
+                    Token::Operator(Op::Div)
+                },
+                '^' => Token::Operator(Op::BitXor),
+                '&' => if self.peek() == '&' {
+                    self.pos += 1;
+                    Token::Operator(Op::And)
+                } else {
+                    Token::Operator(Op::BitAnd)
+                },
 
-                    '|' => if self.peek() == '|' {
-                        self.pos += 1;
-                        Token::Operator(Op::Or_)
-                    } else {
-                        Token::Operator(Op::BitOr)
-                    },
-                    '!' => if self.peek() == '=' {
-                        self.pos += 1;
-                        Token::Operator(Op::Neq)
-                    } else {
-                        Token::Operator(Op::Not)
-                    },
-                    '%' => Token::Operator(Op::Mod),
-                    '(' => Token::Operator(Op::LParens),
-                    ')' => Token::Operator(Op::RParens),
-                    '=' => if self.peek() == '=' {
-                        self.pos += 1;
-                        Token::Operator(Op::Eq_)
-                    } else {
-                        Token::Operator(Op::Assign)
-                    },
-                    '>' => if self.peek() == '=' {
-                        self.pos += 1;
-                        Token::Operator(Op::Geq)
-                    } else {
-                        Token::Operator(Op::Gt_)
-                    },
-                    '<' => if self.peek() == '=' {
-                        self.pos += 1;
-                        Token::Operator(Op::Leq)
-                    } else {
-                        Token::Operator(Op::Lt_)
-                    },
-
-                    // These operators are for symbols such as line and block delimiters.
-                    '\n' | ';' => Token::Operator(Op::LineEnd),
-                    '{' => Token::Operator(Op::BlockStart),
-                    '}' => Token::Operator(Op::BlockEnd),
-                    ',' => Token::Operator(Op::Comma),
-
-                    // We don't recognise the symbol.
-                    _ => panic!(
-                        "get_next: Expected an operator, but got \"{}\" at position {}!",
-                        ch, self.pos
-                    ),
+                '|' => if self.peek() == '|' {
+                    self.pos += 1;
+                    Token::Operator(Op::Or_)
+                } else {
+                    Token::Operator(Op::BitOr)
+                },
+                '!' => if self.peek() == '=' {
+                    self.pos += 1;
+                    Token::Operator(Op::Neq)
+                } else {
+                    Token::Operator(Op::Not)
                 },
-                self.pos,
-            );
+                '%' => Token::Operator(Op::Mod),
+                '(' => Token::Operator(Op::LParens),
+                ')' => Token::Operator(Op::RParens),
+                '[' => Token::Operator(Op::LBracket),
+                ']' => Token::Operator(Op::RBracket),
+                ':' => Token::Operator(Op::Colon),
+                '=' => if self.peek() == '=' {
+                    self.pos += 1;
+                    Token::Operator(Op::Eq_)
+                } else if self.peek() == '>' {
+                    self.pos += 1;
+                    Token::Operator(Op::Arrow)
+                } else {
+                    Token::Operator(Op::Assign)
+                },
+                // '>>>' is a logical shift and '>>' an arithmetic one.
+                '>' => if self.peek() == '>' && self.input.get(self.pos + 2) == Some(&b'>') {
+                    self.pos += 2;
+                    Token::Operator(Op::Lrs)
+                } else if self.peek() == '>' {
+                    self.pos += 1;
+                    Token::Operator(Op::Ars)
+                } else if self.peek() == '=' {
+                    self.pos += 1;
+                    Token::Operator(Op::Geq)
+                } else {
+                    Token::Operator(Op::Gt_)
+                },
+                '<' => if self.peek() == '<' {
+                    self.pos += 1;
+                    Token::Operator(Op::Lls)
+                } else if self.peek() == '=' {
+                    self.pos += 1;
+                    Token::Operator(Op::Leq)
+                } else {
+                    Token::Operator(Op::Lt_)
+                },
+                '~' => Token::Operator(Op::BitNot),
+
+                // These operators are for symbols such as line and block delimiters.
+                '\n' | ';' => Token::Operator(Op::LineEnd),
+                '{' => Token::Operator(Op::BlockStart),
+                '}' => Token::Operator(Op::BlockEnd),
+                ',' => Token::Operator(Op::Comma),
+
+                // We don't recognise the symbol. The whole character is skipped,
+                //   even if it spans several bytes, so lexing can resume after it.
+                _ => {
+                    let c = ::std::str::from_utf8(&self.input[start..])
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(ch);
+
+                    self.pos = start + c.len_utf8();
+                    self.curr = TokStruct::new(Token::Bad, start);
+
+                    return Err(ParseError::new(
+                        ParseErrorKind::UnknownSymbol,
+                        start..self.pos,
+                        Vec::new(),
+                        Token::Other(c),
+                    ));
+                }
+            };
+
             self.pos += 1;
+
+            // An operator directly followed by '=' is a compound assignment, as in 'x += 1'.
+            //   Comparisons like '<=' were already taken care of above.
+            let tok = match tok {
+                Token::Operator(op) => if op.has_compound() && self.input.get(self.pos) == Some(&b'=') {
+                    self.pos += 1;
+                    Token::Operator(Op::CompoundAssign(Box::new(op)))
+                } else {
+                    Token::Operator(op)
+                },
+                t => t,
+            };
+
+            self.curr = TokStruct::new(tok, start);
         }
 
         // Self.curr has been updated by the match block.
-        TokStruct::new(self.curr.get_val(), self.pos)
+        Ok(self.curr.clone())
+    }
+
+    // Skips whitespace and comments. A '#' comment runs to the end of its line, but the
+    //   line end is left alone since it still ends a statement. A '/* */' comment can
+    //   span several lines, and counts as a space. '//' is integer division, so it
+    //   cannot start a comment.
+    fn skip_blank(&mut self) -> PResult<()> {
+        loop {
+            while self.pos < self.input.len() && is_blank(self.input[self.pos] as char) {
+                self.pos += 1;
+            }
+
+            let start = self.pos;
+
+            match (self.input.get(self.pos), self.input.get(self.pos + 1)) {
+                (Some(&b'#'), _) => {
+                    while self.pos < self.input.len() && self.input[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                (Some(&b'/'), Some(&b'*')) => {
+                    match self.input[start + 2..].windows(2).position(|w| w == b"*/") {
+                        Some(i) => self.pos = start + 2 + i + 2,
+                        None => {
+                            self.pos = self.input.len();
+                            self.curr = TokStruct::new(Token::Bad, start);
+
+                            return Err(ParseError::new(
+                                ParseErrorKind::UnterminatedComment,
+                                start..self.pos,
+                                Vec::new(),
+                                Token::Bad,
+                            ));
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
     }
 
     // Returns a Vector containing all the tokens in the input.
-    pub fn get_all(&mut self) -> Vec<Token> {
+    #[cfg(test)]
+    pub fn get_all(&mut self) -> PResult<Vec<Token>> {
         let mut toks: Vec<Token> = Vec::new();
 
-        while self.input[self.pos] as char != '\0' {
-            toks.push(self.get_next().get_val());
+        loop {
+            match self.get_next()?.get_val() {
+                Token::None => break,
+                t => toks.push(t),
+            }
         }
-        toks
+
+        Ok(toks)
     }
 
     // Validates the current token against a provided value and,
     //   on successful validation, updates the current token and
     //   returns the previous token.
-    pub fn eat(&mut self, token: Token) -> TokStruct {
+    pub fn eat(&mut self, token: Token) -> PResult<TokStruct> {
         let t = self.curr.clone();
-        if discriminant(&t.get_val()) == discriminant(&token) {
-            self.curr = self.get_next();
+
+        // Operators and keywords have to match exactly, everything else only by kind.
+        let matches = match (&t.val, &token) {
+            (Token::Operator(x), Token::Operator(y)) => x == y,
+            (Token::KeyWord(x), Token::KeyWord(y)) => x == y,
+            (x, y) => discriminant(x) == discriminant(y),
+        };
+
+        if matches {
+            self.curr = self.get_next()?;
+            Ok(t)
         } else {
-            panic!(
-                "eat: Expected {}, but got {} at position {}!",
-                token,
-                t.get_val(),
-                self.pos
-            );
+            Err(self.unexpected(vec![token]))
         }
+    }
 
-        t
+    // Builds an error for the current token, listing what was expected in its place.
+    pub fn unexpected(&self, expected: Vec<Token>) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::UnexpectedToken,
+            self.curr_span(),
+            expected,
+            self.curr.get_val(),
+        )
     }
 }
 
+// Whitespace that separates tokens. Newlines are not included since they end a line.
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t' || ch == '\r'
+}
+
 // The Parser implementation.
 pub struct Parser {
     lexer: Lexer,
 
     // Might be better to remove this.
     input: String,
+
+    // Errors recovered from so far in the current parse.
+    errors: Vec<ParseError>,
+
+    // How many loops the statement being parsed is nested in, so a
+    //   'break' or 'continue' outside of one can be rejected.
+    loops: usize,
+
+    // How many expressions and statements the one being parsed is nested in.
+    depth: usize,
 }
 
 
@@ -611,6 +1025,9 @@ impl Parser {
         Parser {
             lexer: Lexer::new(),
             input: String::new(),
+            errors: Vec::new(),
+            loops: 0,
+            depth: 0,
         }
     }
 
@@ -623,269 +1040,839 @@ impl Parser {
         self.lexer.base(base);
     }
 
-    // Incomplete temporary eval function to test parse tree generation.
-    pub fn eval(&mut self) -> Program {
-        // We need to reset the lexer before we parse anything.
-        self.lexer.reset();
-        /*
-        if self.input.find(|c: char| c == '>' || c == '<' || c == '!') == None {
-            self.expr()
-        } else {
-            self.bool_expr()
-        }
-        */
-
-        self.program()
-    }
-
-    // Terminal function to accept a number.
-    fn number(&mut self) -> Factor {
-        Factor::Float(self.lexer.eat(Token::Number(0.)))
+    pub fn get_base(&self) -> u32 {
+        self.lexer.get_base()
     }
 
-    // Terminal function to accept an identifier.
-    fn id(&mut self) -> Factor {
-        Factor::Symbol(self.lexer.eat(Token::Var(String::new())))
-    }
+    // Parses the current input into a program. Errors are collected rather than
+    //   aborting the process, so callers can report them and carry on.
+    pub fn eval(&mut self) -> Result<Program, Vec<ParseError>> {
+        let (program, errors) = self.eval_partial();
 
-    // Terminal function to accept a boolean value.
-    fn boolean(&mut self) -> Factor {
-        Factor::Bool(self.lexer.eat(Token::Bool(true)))
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
     }
 
-    // Non terminal function to accept a factor.
-    // A factor is  defined by the rule -
-    //
-    // factor: NUMBER | VARIABLE | fn_call | LPARENS expr RPARENS | POW pow_factor | (POS | NEG) factor
-    pub fn factor(&mut self) -> Expr {
-        let mut t: Expr;
-        let m = self.get_curr();
+    // Parses the current input, recovering from errors where possible. Returns
+    //   whatever could be parsed, with Statement::Error nodes in place of bad
+    //   statements, along with every error found.
+    pub fn eval_partial(&mut self) -> (Program, Vec<ParseError>) {
+        self.errors.clear();
 
-        match m.get_val() {
-            Token::Number(_) => t = Expr::Factor(boxe!(self.number())),
-            Token::Var(_) => t = Expr::Factor(boxe!(self.var_disambiguate())),
-
-            // If we encounter a '(' character, We interpret it as a subexpression.
-            Token::Operator(Op::LParens) => {
-                self.lexer.eat(m.get_val());
-                t = self.expr();
-                self.lexer.eat(Token::Operator(Op::RParens));
-            }
-            Token::Operator(Op::Pow) => {
-                // The power operator is right associative.
-                //
-                // We pass the power operator from pow_factor() without consuming it,
-                //   then consume the operator and parse a nested power factor. If
-                //   the nested expression is again an expression of the form 'a ** b',
-                //   it is evaluated again by passing through pow_factor() and this function
-                //   until there are no power factors left. The resulting AST is structured
-                //   so that all power functions appear on the right subtree of parent nodes.
-                self.lexer.eat(m.get_val());
-                t = self.pow_factor();
-            }
-            Token::Operator(Op::Pos)
-          | Token::Operator(Op::Neg) => {
-                // We first "eat" a '+', then parse what comes after as a factor
-                //   (since it could be a subexpression as well).
-                t = Expr::Unary {
-                    right: boxe!(self.factor()),
-                    op: m.get_val().unwrap_as_op().unwrap()
-                };
-            }
-            // To prevent errors when we get an empty input sequence.
-            Token::None => t = Expr::Factor(boxe!(Factor::None)),
-            _ => panic!(
-                "factor: Expected Number or Variable at position {}, got {}!",
-                m.get_pos(),
-                m.get_val()
-            ),
+        // We need to reset the lexer before we parse anything.
+        if let Err(e) = self.lexer.reset() {
+            self.report(e);
         }
 
-        t
+        let program = self.program();
+
+        (program, std::mem::take(&mut self.errors))
     }
 
-    // A non terminal function to parse expressions of the form 'a ** b', which I call a power factor.
-    // Both 'a' and 'b' are factors.
-    // It can be applied on multileveled expressions such as 'a ** (2 * b ** 4)' as well, through a recursion loop.
-    //
-    // A power factor is represented by the rule-
-    //
-    // pow_factor: factor (POW factor)
-    //
-    // If this function encounters a single factor alone, it directly passes on the result of factor().
-    // If an expression like 'a ** b' is encountered, it passes on the expression without consuming the power operator.
-    // The power operator is consumed by the factor() function.
-    pub fn pow_factor(&mut self) -> Expr {
-        // The first factor is guaranteed to exist.
-        let t = self.factor();
-        let m = self.get_curr();
+    // Parses the input as a single expression, such as a value typed in for 'read'.
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.errors.clear();
+        self.lexer.reset()?;
 
-        match m.get_val() {
-            // If we find Op::Pow, we can continue to get the next power factor.
-            Token::Operator(Op::Pow) => Expr::Binary {
-                left: boxe!(t),
-                right: boxe!(self.factor()),
-                op: Op::Pow
-            },
-            _ => t,
+        let e = self.expr()?;
+
+        match self.get_curr().get_val() {
+            Token::None => Ok(e),
+            _ => Err(self.lexer.unexpected(Vec::new())),
         }
     }
 
-    // A non terminal function to parse expressions of the form 'a * b', 'a / b', 'a // b' or 'a % b'
-    //   where both 'a' and 'b' are power factors.
-    // A term is composed of 1 or more power factors and is defined by the rule-
-    //
-    // term: pow_factor ((MUL | DIV | MOD | INTDIV) pow_factor)*
-    pub fn term(&mut self) -> Expr {
-        let mut t = self.pow_factor();
-        let mut m = self.get_curr();
-
-        // Keep looping while the current operator is any of these.
-        while match m.get_val() {
-            Token::Operator(Op::Mul)
-          | Token::Operator(Op::Div)
-          | Token::Operator(Op::Mod)
-          | Token::Operator(Op::IntDiv) => true,
-            _ => false,
-        } {
-            self.lexer.eat(m.get_val());
-            t = Expr::Binary {
-                left: boxe!(t),
-                right: boxe!(self.pow_factor()),
-                op: m.get_val().unwrap_as_op().unwrap()
-            };
-            m = self.get_curr();
+    // Parses a single line typed at the REPL. Unlike a program, a line can hold
+    //   bare expressions, assignments and function declarations at the top level.
+    pub fn parse_repl_line(&mut self) -> Result<Vec<Statement>, Vec<ParseError>> {
+        self.errors.clear();
+
+        if let Err(e) = self.lexer.reset() {
+            self.report(e);
         }
 
-        t
-    }
+        let mut t = Vec::new();
 
-    // A non terminal function to parse expressions of the form 'a + b' or 'a - b'.
-    // It functions similarly to term().
-    //
-    // An expression is represented by the rule-
-    //
-    // expr: term ((ADD | SUB) term)*
+        loop {
+            match self.get_curr().get_val() {
+                Token::None => break,
 
-    pub fn expr(&mut self) -> Expr {
-        let mut t = self.term();
-        let mut m = self.get_curr();
+                // There is no block to close at the top level.
+                Token::Operator(Op::BlockEnd) => {
+                    let e = self.lexer.unexpected(Vec::new());
+                    self.report(e);
+                    self.advance();
+                }
 
-        while match m.get_val() {
-            Token::Operator(Op::Add) | Token::Operator(Op::Sub) => true,
-            _ => false,
-        } {
-            self.lexer.eat(m.get_val());
-            t = Expr::Binary {
-                left: boxe!(t),
-                right: boxe!(self.term()),
-                op: m.get_val().unwrap_as_op().unwrap()
-            };
-            m = self.get_curr();
-        }
+                _ => {
+                    let start = self.get_curr().get_pos();
 
-        t
+                    match self.statement() {
+                        Ok(s) => t.push(s),
+                        Err(e) => {
+                            self.report(e);
+                            self.recover(start);
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(t)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    // Records an error. An error at the same position as the previous one is
+    //   a knock-on effect of it, so it is dropped.
+    fn report(&mut self, e: ParseError) {
+        match self.errors.last() {
+            Some(l) if l.span.start == e.span.start => {}
+            _ => self.errors.push(e),
+        }
+    }
+
+    // Moves on to the next token, recording any lexer error along the way.
+    fn advance(&mut self) {
+        if let Err(e) = self.lexer.get_next() {
+            self.report(e);
+        }
+    }
+
+    // Panic mode error recovery. Skips tokens until parsing can safely resume;
+    //   just past a line end, or at a block end or 'fn' keyword. Blocks opened
+    //   while skipping are skipped whole, so their contents do not stop recovery early.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        loop {
+            match self.get_curr().get_val() {
+                Token::None => return,
+                Token::Operator(Op::LineEnd) if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                Token::Operator(Op::BlockEnd) if depth == 0 => return,
+                Token::KeyWord(ref x) if depth == 0 && x == "fn" => return,
+                Token::Operator(Op::BlockStart) => depth += 1,
+                Token::Operator(Op::BlockEnd) => depth -= 1,
+                _ => {}
+            }
+
+            self.advance();
+        }
+    }
+
+    // Runs a parsing function one level deeper, failing once the input nests so deeply
+    //   that carrying on could overflow the stack.
+    fn nested<T, F: FnOnce(&mut Parser) -> PResult<T>>(&mut self, parse: F) -> PResult<T> {
+        if self.depth >= MAX_NESTING {
+            return Err(ParseError::new(
+                ParseErrorKind::TooDeep,
+                self.lexer.curr_span(),
+                Vec::new(),
+                self.get_curr().get_val(),
+            ));
+        }
+
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+
+        res
+    }
+
+    // Recovers from an error in a statement that started at 'start'. If skipping ahead
+    //   stopped on that same token, as it does on 'fn', the token is skipped too, so a
+    //   statement that fails without consuming anything is not retried forever.
+    fn recover(&mut self, start: usize) {
+        self.synchronize();
+
+        if self.get_curr().get_pos() == start && self.get_curr().get_val() != Token::None {
+            self.advance();
+        }
+    }
+
+    // Terminal function to accept a number.
+    fn number(&mut self) -> PResult<Factor> {
+        match self.get_curr().get_val() {
+            Token::Int(_) => Ok(Factor::Int(self.lexer.eat(Token::Int(BigInt::default()))?)),
+            Token::Imag(_) => Ok(Factor::Imag(self.lexer.eat(Token::Imag(0.))?)),
+            _ => Ok(Factor::Float(self.lexer.eat(Token::Number(BigDecimal::default()))?)),
+        }
+    }
+
+    // Terminal function to accept an identifier.
+    fn id(&mut self) -> PResult<Factor> {
+        Ok(Factor::Symbol(self.lexer.eat(Token::Var(String::new()))?))
+    }
+
+    // Terminal function to accept a boolean value.
+    fn boolean(&mut self) -> PResult<Factor> {
+        Ok(Factor::Bool(self.lexer.eat(Token::Bool(true))?))
+    }
+
+    // Non terminal function to accept the indexes, slices and calls that can follow a factor,
+    //   as in 's[0]', 's[-1]', 's[1:3]' or 'f(x)'. They can be chained, as in 'a[0][1]'
+    //   or 'adder(1)(2)'.
+    //
+    // postfix: (LBRACKET (expr | (expr)? COLON (expr)? (COLON (expr)?)?) RBRACKET | arg_list)*
+    fn postfix(&mut self, mut t: Expr) -> PResult<Expr> {
+        loop {
+            match self.get_curr().get_val() {
+                Token::Operator(Op::LBracket) => {}
+                Token::Operator(Op::LParens) => {
+                    t = Expr::Call {
+                        target: boxe!(t),
+                        args: self.arg_list()?
+                    };
+                    continue;
+                }
+                _ => break,
+            }
+
+            self.lexer.eat(Token::Operator(Op::LBracket))?;
+
+            let start = self.slice_part()?;
+
+            if self.get_curr().get_val() != Token::Operator(Op::Colon) {
+                let index = match start {
+                    Some(i) => i,
+                    None => return Err(self.lexer.unexpected(vec![
+                        Token::Number(BigDecimal::default()),
+                        Token::Var(String::new()),
+                        Token::Operator(Op::Colon),
+                    ])),
+                };
+
+                self.lexer.eat(Token::Operator(Op::RBracket))?;
+                t = Expr::Index {
+                    target: boxe!(t),
+                    index
+                };
+                continue;
+            }
+
+            self.lexer.eat(Token::Operator(Op::Colon))?;
+            let end = self.slice_part()?;
+
+            let step = if self.get_curr().get_val() == Token::Operator(Op::Colon) {
+                self.lexer.eat(Token::Operator(Op::Colon))?;
+                self.slice_part()?
+            } else {
+                None
+            };
+
+            self.lexer.eat(Token::Operator(Op::RBracket))?;
+            t = Expr::Slice {
+                target: boxe!(t),
+                start,
+                end,
+                step
+            };
+        }
+
+        Ok(t)
+    }
+
+    // Non terminal function to accept a list literal. A trailing comma is allowed.
+    //
+    // array_literal: LBRACKET (expr (COMMA expr)* (COMMA)?)? RBRACKET
+    fn array_literal(&mut self) -> PResult<ArrayLiteral> {
+        let mut t = ArrayLiteral {
+            start: self.lexer.eat(Token::Operator(Op::LBracket))?,
+            items: Vec::new(),
+        };
+
+        while self.get_curr().get_val() != Token::Operator(Op::RBracket) {
+            t.items.push(self.expr()?);
+
+            match self.get_curr().get_val() {
+                Token::Operator(Op::Comma) => {
+                    self.lexer.eat(Token::Operator(Op::Comma))?;
+                }
+                Token::Operator(Op::RBracket) => {}
+                _ => return Err(self.lexer.unexpected(vec![
+                    Token::Operator(Op::Comma),
+                    Token::Operator(Op::RBracket),
+                ])),
+            }
+        }
+
+        self.lexer.eat(Token::Operator(Op::RBracket))?;
+        Ok(t)
+    }
+
+    // Non terminal function to accept a map literal. A trailing comma is allowed.
+    //
+    // map_literal: BLOCK_START (expr COLON expr (COMMA expr COLON expr)* (COMMA)?)? BLOCK_END
+    fn map_literal(&mut self) -> PResult<MapLiteral> {
+        let mut t = MapLiteral {
+            start: self.lexer.eat(Token::Operator(Op::BlockStart))?,
+            items: Vec::new(),
+        };
+
+        // A map can be spread over several lines, so line ends inside it are skipped.
+        self.skip_line_ends()?;
+
+        while self.get_curr().get_val() != Token::Operator(Op::BlockEnd) {
+            let key = self.expr()?;
+            self.lexer.eat(Token::Operator(Op::Colon))?;
+            t.items.push((key, self.expr()?));
+            self.skip_line_ends()?;
+
+            match self.get_curr().get_val() {
+                Token::Operator(Op::Comma) => {
+                    self.lexer.eat(Token::Operator(Op::Comma))?;
+                    self.skip_line_ends()?;
+                }
+                Token::Operator(Op::BlockEnd) => {}
+                _ => return Err(self.lexer.unexpected(vec![
+                    Token::Operator(Op::Comma),
+                    Token::Operator(Op::BlockEnd),
+                ])),
+            }
+        }
+
+        self.lexer.eat(Token::Operator(Op::BlockEnd))?;
+        Ok(t)
+    }
+
+    fn skip_line_ends(&mut self) -> PResult<()> {
+        while self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
+        }
+
+        Ok(())
+    }
+
+    // Whether a '{' at the start of a statement opens a map rather than a block. It does if
+    //   an expression and then a ':' follow, as in '{ "a" + b: 1 }', which can never start a
+    //   block. This is found out by parsing the expression and then going back to the '{',
+    //   forgetting any errors on the way. Anywhere else an expression is expected, '{'
+    //   always opens a map. '{}' on its own is an empty block.
+    fn at_map_literal(&mut self) -> PResult<bool> {
+        let pos = self.lexer.get_pos();
+        let curr = self.lexer.get_curr();
+        let errors = self.errors.len();
+        let loops = self.loops;
+
+        self.lexer.eat(Token::Operator(Op::BlockStart))?;
+        let is_map = match self.expr() {
+            Ok(_) => self.get_curr().get_val() == Token::Operator(Op::Colon),
+            Err(_) => false,
+        };
+
+        self.lexer.set_pos(pos);
+        self.lexer.set_curr(curr);
+        self.errors.truncate(errors);
+        self.loops = loops;
+
+        Ok(is_map)
+    }
+
+    // One part of a slice, which is left out if a ':' or ']' comes first.
+    fn slice_part(&mut self) -> PResult<Option<Box<Expr>>> {
+        match self.get_curr().get_val() {
+            Token::Operator(Op::Colon) | Token::Operator(Op::RBracket) => Ok(None),
+            _ => Ok(Some(boxe!(self.expr()?))),
+        }
+    }
+
+    // Non terminal function to accept a factor, which is a single value with any
+    //   indexes, slices or calls after it. Operators are handled by unary() and binary_expr().
+    //
+    // factor: (NUMBER | STRING | BOOL | VARIABLE | fn_call | array_literal | map_literal | lambda | if_expr | parenthesized) postfix
+    pub fn factor(&mut self) -> PResult<Expr> {
+        let m = self.get_curr();
+
+        let t = match m.get_val() {
+            Token::Int(_) | Token::Number(_) | Token::Imag(_) => Expr::Factor(boxe!(self.number()?)),
+            Token::Str(_) => Expr::Factor(boxe!(Factor::String(self.lexer.eat(Token::Str(String::new()))?))),
+            Token::Bool(_) => Expr::Factor(boxe!(self.boolean()?)),
+            Token::Operator(Op::LBracket) => Expr::Factor(boxe!(Factor::Array(self.array_literal()?))),
+            Token::Operator(Op::BlockStart) => Expr::Factor(boxe!(Factor::Map(self.map_literal()?))),
+            Token::Var(_) => Expr::Factor(boxe!(self.var_disambiguate()?)),
+            Token::Operator(Op::BitOr) | Token::Operator(Op::Or_) => Expr::Factor(boxe!(self.lambda()?)),
+            Token::KeyWord(ref k) if k == "fn" => Expr::Factor(boxe!(self.lambda()?)),
+            Token::KeyWord(ref k) if k == "if" => self.if_expr()?,
+
+            // If we encounter a '(' character, We interpret it as a subexpression,
+            //   or a tuple if there is a comma inside.
+            Token::Operator(Op::LParens) => self.parenthesized()?,
+            _ => return Err(self.lexer.unexpected(vec![
+                Token::Number(BigDecimal::default()),
+                Token::Var(String::new()),
+                Token::Operator(Op::LParens),
+            ])),
+        };
+
+        self.postfix(t)
+    }
+
+    // Non terminal function to accept 'if' used as an expression, as in
+    //   'y = if x < 0 { -x } else { x }'. Each branch holds a single expression, and since
+    //   the whole thing has to give a value, the 'else' cannot be left out. It becomes the
+    //   same Expr::Conditional as 'a if cond else b', so only the chosen branch is evaluated.
+    //
+    // if_expr: (IF | ELIF) expr braced_expr (if_expr | ELSE (if_expr | braced_expr))
+    fn if_expr(&mut self) -> PResult<Expr> {
+        let keyword = self.get_curr().get_val();
+        self.lexer.eat(keyword)?;
+
+        let cond = self.expr()?;
+        let if_true = self.braced_expr()?;
+
+        let if_false = match self.get_curr().get_val() {
+            Token::KeyWord(ref k) if k == "elif" => self.if_expr()?,
+            _ => {
+                self.lexer.eat(KEYWORD_TABLE["else"].clone())?;
+
+                if self.get_curr().get_val() == KEYWORD_TABLE["if"] {
+                    self.if_expr()?
+                } else {
+                    self.braced_expr()?
+                }
+            }
+        };
+
+        Ok(Expr::Conditional {
+            cond: boxe!(cond),
+            if_true: boxe!(if_true),
+            if_false: boxe!(if_false)
+        })
+    }
+
+    // A single expression in braces, which is one branch of an if_expr.
+    //
+    // braced_expr: BLOCK_START expr BLOCK_END
+    fn braced_expr(&mut self) -> PResult<Expr> {
+        self.lexer.eat(Token::Operator(Op::BlockStart))?;
+        self.skip_line_ends()?;
+        let t = self.expr()?;
+        self.skip_line_ends()?;
+        self.lexer.eat(Token::Operator(Op::BlockEnd))?;
+
+        Ok(t)
+    }
+
+    // Non terminal function to accept an anonymous function. The short form takes a
+    //   single expression, as in '|x, y| x * y', and '||' starts one with no parameters.
+    //   The long form takes an expression after '=>' or a block, as in
+    //   'fn(x) => x ** 2' or 'fn(x) { return x ** 2; }'.
+    //
+    // lambda: BIT_OR (VARIABLE (COMMA VARIABLE)*)? BIT_OR expr | OR expr
+    //       | FN args (ARROW expr | scope)
+    fn lambda(&mut self) -> PResult<Factor> {
+        let m = self.get_curr();
+        let mut args = ArgDeclList::default();
+
+        let arrow = match m.get_val() {
+            Token::Operator(Op::Or_) => {
+                self.lexer.eat(m.get_val())?;
+                true
+            }
+            Token::Operator(Op::BitOr) => {
+                self.lexer.eat(m.get_val())?;
+
+                // Parameters here cannot have defaults, since a default like 'y = 2|'
+                //   would swallow the closing '|'. The 'fn' form allows them.
+                while self.get_curr().get_val() != Token::Operator(Op::BitOr) {
+                    args.argv.push(Param {
+                        name: self.lexer.eat(Token::Var(String::new()))?,
+                        default: None
+                    });
+
+                    match self.get_curr().get_val() {
+                        Token::Operator(Op::Comma) => {
+                            self.lexer.eat(Token::Operator(Op::Comma))?;
+                        }
+                        Token::Operator(Op::BitOr) => {}
+                        _ => return Err(self.lexer.unexpected(vec![
+                            Token::Operator(Op::Comma),
+                            Token::Operator(Op::BitOr),
+                        ])),
+                    }
+                }
+
+                self.lexer.eat(Token::Operator(Op::BitOr))?;
+                true
+            }
+            _ => {
+                self.lexer.eat(KEYWORD_TABLE["fn"].clone())?;
+                args = self.arg_decl_list()?;
+
+                if self.get_curr().get_val() == Token::Operator(Op::Arrow) {
+                    self.lexer.eat(Token::Operator(Op::Arrow))?;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        // Like a function declaration, a lambda cannot break out of a loop around it.
+        let loops = self.loops;
+        self.loops = 0;
+        let body = if arrow {
+            self.expr().map(|e| vec![Statement::Return { val: e }])
+        } else {
+            self.scope()
+        };
+        self.loops = loops;
+
+        Ok(Factor::Lambda(Function {
+            name: TokStruct::new(Token::Var("lambda".to_owned()), m.get_pos()),
+            args,
+            body: Scope {
+                contents: body?
+            }
+        }))
+    }
+
+    // Non terminal function to accept a parenthesized expression or tuple. '()' is the empty
+    //   tuple, and a trailing comma makes a tuple of one, as in '(1,)'.
+    //
+    // parenthesized: LPARENS (expr | (expr COMMA)+ (expr)?)? RPARENS
+    fn parenthesized(&mut self) -> PResult<Expr> {
+        let start = self.lexer.eat(Token::Operator(Op::LParens))?;
+
+        if self.get_curr().get_val() == Token::Operator(Op::RParens) {
+            self.lexer.eat(Token::Operator(Op::RParens))?;
+            return Ok(Expr::Factor(boxe!(Factor::Tuple(TupleLiteral {
+                start,
+                items: Vec::new()
+            }))));
+        }
+
+        let first = self.expr()?;
+
+        if self.get_curr().get_val() != Token::Operator(Op::Comma) {
+            self.lexer.eat(Token::Operator(Op::RParens))?;
+            return Ok(first);
+        }
+
+        let mut t = TupleLiteral {
+            start,
+            items: vec![first],
+        };
+
+        while self.get_curr().get_val() == Token::Operator(Op::Comma) {
+            self.lexer.eat(Token::Operator(Op::Comma))?;
+
+            if self.get_curr().get_val() == Token::Operator(Op::RParens) {
+                break;
+            }
+
+            t.items.push(self.expr()?);
+        }
+
+        self.lexer.eat(Token::Operator(Op::RParens))?;
+        Ok(Expr::Factor(boxe!(Factor::Tuple(t))))
+    }
+
+    // An expression, or several separated by commas, which make a tuple without needing
+    //   parentheses. This is used where a list of values cannot be mistaken for
+    //   anything else: statements, the right side of assignments and 'return'.
+    //
+    // tuple_expr: expr (COMMA expr)*
+    fn tuple_expr(&mut self) -> PResult<Expr> {
+        let start = self.get_curr();
+        let first = self.expr()?;
+
+        if self.get_curr().get_val() != Token::Operator(Op::Comma) {
+            return Ok(first);
+        }
+
+        let mut t = TupleLiteral {
+            start,
+            items: vec![first],
+        };
+
+        while self.get_curr().get_val() == Token::Operator(Op::Comma) {
+            self.lexer.eat(Token::Operator(Op::Comma))?;
+            t.items.push(self.expr()?);
+        }
+
+        Ok(Expr::Factor(boxe!(Factor::Tuple(t))))
+    }
+
+    // A non terminal function to parse powers, as in 'a ** b'. The power operator is right
+    //   associative, so 'a ** b ** c' is 'a ** (b ** c)', and it binds tighter than a sign on
+    //   its left but not on its right, so '-a ** 2' is '-(a ** 2)' and '2 ** -1' works.
+    //
+    // power: factor (POW unary)?
+    fn power(&mut self) -> PResult<Expr> {
+        let t = self.factor()?;
+
+        if self.get_curr().get_val() != Token::Operator(Op::Pow) {
+            return Ok(t);
+        }
+
+        self.lexer.eat(Token::Operator(Op::Pow))?;
+        Ok(Expr::Binary {
+            left: boxe!(t),
+            right: boxe!(self.unary()?),
+            op: Op::Pow
+        })
+    }
+
+    // A non terminal function to parse prefix operators.
+    //
+    // '!' is looser than the others. Like python's 'not', it applies to a whole
+    //   comparison, so '!a == b' is '!(a == b)', while '-a * b' is '(-a) * b'.
+    //
+    // unary: NOT binary_expr(comparison) | (POS | NEG | BIT_NOT) unary | power
+    fn unary(&mut self) -> PResult<Expr> {
+        self.nested(Parser::prefixed)
+    }
+
+    fn prefixed(&mut self) -> PResult<Expr> {
+        let m = self.get_curr();
+
+        match m.get_val() {
+            Token::Operator(Op::Not) => {
+                self.lexer.eat(m.get_val())?;
+                Ok(Expr::Unary {
+                    right: boxe!(self.binary_expr(COMPARISON_PRECEDENCE)?),
+                    op: Op::Not
+                })
+            }
+            Token::Operator(Op::Pos)
+          | Token::Operator(Op::Neg)
+          | Token::Operator(Op::BitNot) => {
+                self.lexer.eat(m.get_val())?;
+                Ok(Expr::Unary {
+                    right: boxe!(self.unary()?),
+                    op: m.get_val().unwrap_as_op().unwrap()
+                })
+            }
+            _ => self.power(),
+        }
+    }
+
+    // The entry point for expressions of any kind, from arithmetic to comparisons and logic.
+    //
+    // A conditional expression binds loosest of all, as in python, so
+    //   'a + 1 if a > 0 else -a' is '(a + 1) if (a > 0) else (-a)'. The else part
+    //   can be another conditional, which gives a chain of cases:
+    //   'sign = -1 if x < 0 else 0 if x == 0 else 1'.
+    //
+    // expr: binary_expr(0) (IF binary_expr(0) ELSE expr)?
+    pub fn expr(&mut self) -> PResult<Expr> {
+        self.nested(Parser::conditional)
+    }
+
+    fn conditional(&mut self) -> PResult<Expr> {
+        let t = self.binary_expr(0)?;
+
+        if self.get_curr().get_val() != KEYWORD_TABLE["if"] {
+            return Ok(t);
+        }
+
+        self.lexer.eat(KEYWORD_TABLE["if"].clone())?;
+        let cond = self.binary_expr(0)?;
+        self.lexer.eat(KEYWORD_TABLE["else"].clone())?;
+
+        Ok(Expr::Conditional {
+            cond: boxe!(cond),
+            if_true: boxe!(t),
+            if_false: boxe!(self.expr()?)
+        })
+    }
+
+    // Parses binary operators by precedence climbing. Each operator has a precedence (see
+    //   binary_precedence()), and this parses a chain of operators that bind at least as
+    //   tightly as min. The right side of each operator is parsed with a higher minimum, so
+    //   tighter operators nest below it and every operator here is left associative.
+    //
+    // binary_expr(min): unary (OP binary_expr(precedence(OP) + 1))*   where precedence(OP) >= min
+    fn binary_expr(&mut self, min: u8) -> PResult<Expr> {
+        let mut t = self.unary()?;
+
+        loop {
+            let m = self.get_curr();
+            let prec = match m.get_val() {
+                Token::Operator(ref o) => match binary_precedence(o) {
+                    Some(p) if p >= min => p,
+                    _ => break,
+                },
+                _ => break,
+            };
+
+            self.lexer.eat(m.get_val())?;
+            let op = m.get_val().unwrap_as_op().unwrap();
+            let right = self.binary_expr(prec + 1)?;
+
+            t = if prec == COMPARISON_PRECEDENCE && self.at_comparison() {
+                self.comparison_chain(t, op, right)?
+            } else {
+                Expr::Binary {
+                    left: boxe!(t),
+                    right: boxe!(right),
+                    op
+                }
+            };
+        }
+
+        Ok(t)
+    }
+
+    fn at_comparison(&self) -> bool {
+        match self.get_curr().get_val() {
+            Token::Operator(ref o) => binary_precedence(o) == Some(COMPARISON_PRECEDENCE),
+            _ => false,
+        }
+    }
+
+    // Collects the rest of a chain of comparisons like 'a < b <= c' into a single node,
+    //   given the first comparison. 'a < b < c' is not '(a < b) < c', which would
+    //   compare a boolean with c.
+    //
+    // comparison_chain: binary_expr(comparison + 1) (COMPARISON binary_expr(comparison + 1))+
+    fn comparison_chain(&mut self, first: Expr, op: Op, second: Expr) -> PResult<Expr> {
+        let mut rest = vec![(op, second)];
+
+        while self.at_comparison() {
+            let m = self.lexer.eat(self.get_curr().get_val())?;
+            rest.push((m.get_val().unwrap_as_op().unwrap(), self.binary_expr(COMPARISON_PRECEDENCE + 1)?));
+        }
+
+        Ok(Expr::Compare {
+            first: boxe!(first),
+            rest
+        })
     }
 
     // A non terminal function representing a comma separated arguement declaration list.
     //   An arguement declaration list must begin with a left parenthesis, and end with
     //   a right parenthesis. An arguement declaration list can only appear within a
     //   function defenition.
-    fn arg_decl_list(&mut self) -> ArgDeclList {
-        self.lexer.eat(Token::Operator(Op::LParens));
+    //
+    // A parameter can have a default value, as in 'tol = 1e-9', and every parameter
+    //   after it must have one too. A last parameter like '*args' takes any extra arguments.
+    //
+    // args: LPARENS (param (COMMA param)* (COMMA MUL VARIABLE)? | MUL VARIABLE)? RPARENS
+    fn arg_decl_list(&mut self) -> PResult<ArgDeclList> {
+        self.lexer.eat(Token::Operator(Op::LParens))?;
 
-        let mut m = self.get_curr();
         let mut t = ArgDeclList::default();
 
-        while match m.get_val() {
-            Token::Operator(Op::RParens) => false,
-            Token::Var(_) => {
-                m = self.lexer.eat(Token::Var(String::new()));
-                true
+        // An empty argument list.
+        if self.get_curr().get_val() == Token::Operator(Op::RParens) {
+            self.lexer.eat(Token::Operator(Op::RParens))?;
+            return Ok(t);
+        }
+
+        loop {
+            if self.get_curr().get_val() == Token::Operator(Op::Mul) {
+                self.lexer.eat(Token::Operator(Op::Mul))?;
+                t.rest = Some(self.lexer.eat(Token::Var(String::new()))?);
+                break;
             }
-            Token::Operator(Op::Comma) => {
-                self.lexer.eat(Token::Operator(Op::Comma));
-                let temp = self.lexer.peek_token();
-                match temp.get_val() {
-                    Token::Operator(Op::RParens) => {
-                        m = self.lexer.eat(Token::Var(String::new()));
-                        true
-                    }
-                    Token::Operator(Op::Comma) => {
-                        m = self.lexer.eat(Token::Var(String::new()));
-                        true
-                    }
-                    _ => panic!(
-                        "Expected Comma or R-parens, got {} at position {}!",
-                        temp.get_val(),
-                        temp.get_pos()
-                    ),
+
+            let name = self.lexer.eat(Token::Var(String::new()))?;
+            let after_default = t.argv.last().is_some_and(|p| p.default.is_some());
+
+            let default = if after_default || self.get_curr().get_val() == Token::Operator(Op::Assign) {
+                self.lexer.eat(Token::Operator(Op::Assign))?;
+                Some(self.expr()?)
+            } else {
+                None
+            };
+
+            t.argv.push(Param {
+                name,
+                default
+            });
+
+            match self.get_curr().get_val() {
+                Token::Operator(Op::Comma) => {
+                    self.lexer.eat(Token::Operator(Op::Comma))?;
                 }
+                Token::Operator(Op::RParens) => break,
+                _ => return Err(self.lexer.unexpected(vec![
+                    Token::Operator(Op::Comma),
+                    Token::Operator(Op::RParens),
+                ])),
             }
-            _ => panic!(
-                "Expected Var, got {} at position {}!",
-                m.get_val(),
-                m.get_pos()
-            ),
-        } {
-            t.argv.push(m);
-            m = self.get_curr();
         }
 
-        self.lexer.eat(Token::Operator(Op::RParens));
+        self.lexer.eat(Token::Operator(Op::RParens))?;
 
-        t
+        Ok(t)
     }
 
     // A non terminal function representing a comma separated arguement list.
     //   An arguement list must begin with a left parenthesis, and end with
     //   a right parenthesis. An arguement list can only appear within a
-    //   function call and may contain only factors.
-    fn arg_list(&mut self) -> ArgList {
-        self.lexer.eat(Token::Operator(Op::LParens));
+    //   function call. An arguement can be given by name, as in 'tol = 1e-6'.
+    fn arg_list(&mut self) -> PResult<ArgList> {
+        self.lexer.eat(Token::Operator(Op::LParens))?;
 
-        let mut m = self.get_curr();
-        let mut tmp = Expr::Factor(boxe!(Factor::None));
         let mut t = ArgList::default();
 
-        while match m.get_val() {
-            Token::Operator(Op::RParens) => false,
-            Token::Var(_) => {
-                tmp = self.factor();
-                true
-            }
-            Token::Operator(Op::Comma) => {
-                self.lexer.eat(Token::Operator(Op::Comma));
-                let temp = self.lexer.peek_token();
-                match temp.get_val() {
-                    Token::Operator(Op::RParens) => {
-                        tmp = self.factor();
-                        true
-                    }
-                    Token::Operator(Op::Comma) => {
-                        tmp = self.factor();
-                        true
+        loop {
+            match self.get_curr().get_val() {
+                Token::Operator(Op::RParens) => break,
+                Token::Var(_) if self.lexer.peek_token()?.get_val() == Token::Operator(Op::Assign) => {
+                    let name = self.lexer.eat(Token::Var(String::new()))?;
+                    self.lexer.eat(Token::Operator(Op::Assign))?;
+                    t.kwargs.push((name, self.expr()?));
+                }
+                Token::Operator(Op::LParens)
+              | Token::Operator(Op::Pos)
+              | Token::Operator(Op::Neg)
+              | Token::Operator(Op::BitNot)
+              | Token::Operator(Op::Not)
+              | Token::Var(_)
+              | Token::Int(_)
+              | Token::Number(_)
+              | Token::Imag(_)
+              | Token::Str(_)
+              | Token::Bool(_)
+              | Token::Operator(Op::BitOr)
+              | Token::Operator(Op::Or_)
+              | Token::KeyWord(_)
+              | Token::Operator(Op::BlockStart)
+              | Token::Operator(Op::LBracket) => {
+                    // Positional arguments are matched up in order, so they all have to come first.
+                    if !t.kwargs.is_empty() {
+                        return Err(ParseError::new(
+                            ParseErrorKind::PositionalAfterKeyword,
+                            self.lexer.curr_span(),
+                            Vec::new(),
+                            self.get_curr().get_val(),
+                        ));
                     }
-                    _ => panic!(
-                        "Expected Comma or R-parens, got {} at position {}!",
-                        temp.get_val(),
-                        temp.get_pos()
-                    ),
+                    t.argv.push(self.expr()?)
+                }
+                _ => return Err(self.lexer.unexpected(vec![
+                    Token::Number(BigDecimal::default()),
+                    Token::Var(String::new()),
+                    Token::Operator(Op::RParens),
+                ])),
+            };
+            match self.get_curr().get_val() {
+                Token::Operator(Op::Comma) => {
+                    self.lexer.eat(Token::Operator(Op::Comma))?;
                 }
+                Token::Operator(Op::RParens) => break,
+                _ => return Err(self.lexer.unexpected(vec![
+                    Token::Operator(Op::Comma),
+                    Token::Operator(Op::RParens),
+                ])),
             }
-            _ => panic!(
-                "Expected Var, got {} at position {}!",
-                m.get_val(),
-                m.get_pos()
-            ),
-        } {
-            t.argv.push(tmp.clone());
-            m = self.get_curr();
         }
 
-        self.lexer.eat(Token::Operator(Op::RParens));
+        self.lexer.eat(Token::Operator(Op::RParens))?;
 
-        t
+        Ok(t)
     }
 
     // fn a (x, y, z) { if x == y return z * 2; else return z / 2; } fn b (l, m) { if a(l, m, 2) > 2 { x = 3; y = 16;  m = (l * x) / y; } else m = 2; return m; }
@@ -895,50 +1882,94 @@ impl Parser {
     //   followed by a parenthesis enclosed set of comma separated
     //   arguements, and then the body of the function enclosed in
     //   block start and end tokens.
-    fn function(&mut self) -> Function {
-        self.lexer.eat(KEYWORD_TABLE["fn"].clone());
-        Function {
-            name: self.lexer.eat(Token::Var(String::new())),
-            args: self.arg_decl_list(),
+    fn function(&mut self) -> PResult<Function> {
+        self.lexer.eat(KEYWORD_TABLE["fn"].clone())?;
+        let name = self.lexer.eat(Token::Var(String::new()))?;
+        let args = self.arg_decl_list()?;
+
+        // A loop around a function declaration cannot be broken out of from inside it.
+        let loops = self.loops;
+        self.loops = 0;
+        let body = self.scope();
+        self.loops = loops;
+
+        Ok(Function {
+            name,
+            args,
             body: Scope {
-                contents: self.scope()
+                contents: body?
             }
-        }
+        })
     }
 
-    // A statement block.
-    fn scope(&mut self) -> Vec<Statement> {
-        self.lexer.eat(Token::Operator(Op::BlockStart));
-        let mut t = vec![self.statement()];
+    // A statement block. A statement that fails to parse is replaced by an
+    //   error node, and parsing carries on with the next statement.
+    fn scope(&mut self) -> PResult<Vec<Statement>> {
+        self.lexer.eat(Token::Operator(Op::BlockStart))?;
+        let mut t = Vec::new();
 
-        while match self.get_curr().get_val() {
-            Token::Operator(Op::BlockEnd) => {
-                self.lexer.eat(Token::Operator(Op::BlockEnd));
-                false
+        loop {
+            match self.get_curr().get_val() {
+                Token::Operator(Op::BlockEnd) => {
+                    self.lexer.eat(Token::Operator(Op::BlockEnd))?;
+                    break;
+                }
+                Token::None => return Err(self.lexer.unexpected(vec![Token::Operator(Op::BlockEnd)])),
+                _ => {
+                    let start = self.get_curr().get_pos();
+
+                    match self.statement() {
+                        Ok(s) => t.push(s),
+                        Err(e) => {
+                            t.push(Statement::Error(e.clone()));
+                            self.report(e);
+                            self.recover(start);
+                        }
+                    }
+                }
             }
-            _ => true,
-        } {
-            t.push(self.statement());
         }
 
-        t
+        Ok(t)
     }
 
     // A statement.
-    fn statement(&mut self) -> Statement {
+    fn statement(&mut self) -> PResult<Statement> {
+        self.nested(Parser::bare_statement)
+    }
+
+    fn bare_statement(&mut self) -> PResult<Statement> {
 
         match self.get_curr().get_val() {
-            // If it's a nested block.
-            Token::Operator(Op::BlockStart) => {
-                Statement::Scope( Scope {
-                    contents: self.scope()
-                })
-            }
+            // If it's a nested block, or a map literal.
+            Token::Operator(Op::BlockStart) => if self.at_map_literal()? {
+                self.expr_statement()
+            } else {
+                Ok(Statement::Scope( Scope {
+                    contents: self.scope()?
+                }))
+            },
 
-            Token::Var(_) => self.assign_statement(),
+            // A variable followed by an assignment operator starts an assignment.
+            Token::Var(_) => match self.lexer.peek_token()?.get_val() {
+                Token::Operator(Op::Assign) | Token::Operator(Op::CompoundAssign(_)) => self.assign_statement(),
+                _ => self.expr_statement(),
+            },
 
             // If it is a variable or number, it's an expression.
-            Token::Operator(Op::LParens) => Statement::Expr(self.expr()),
+            Token::Int(_)
+          | Token::Number(_)
+          | Token::Imag(_)
+          | Token::Str(_)
+          | Token::Bool(_)
+          | Token::Operator(Op::LParens)
+          | Token::Operator(Op::LBracket)
+          | Token::Operator(Op::BitOr)
+          | Token::Operator(Op::Or_)
+          | Token::Operator(Op::Pos)
+          | Token::Operator(Op::Neg)
+          | Token::Operator(Op::BitNot)
+          | Token::Operator(Op::Not) => self.expr_statement(),
 
             // Various keywords.
             Token::KeyWord(x) => if &x == "if" {
@@ -946,83 +1977,191 @@ impl Parser {
             } else if &x == "return" {
                 self.return_statement()
             } else if &x == "fn" {
-                Statement::FnDecl(self.function())
+                // 'fn' followed by a name declares a function, otherwise it starts a lambda.
+                match self.lexer.peek_token()?.get_val() {
+                    Token::Var(_) => Ok(Statement::FnDecl(self.function()?)),
+                    _ => self.expr_statement(),
+                }
+            } else if &x == "array" {
+                self.array_statement()
+            } else if &x == "state" {
+                self.state_statement()
+            } else if &x == "while" {
+                self.while_statement()
+            } else if &x == "for" {
+                self.for_statement()
+            } else if &x == "break" || &x == "continue" {
+                self.loop_control_statement(&x)
+            } else if &x == "write" {
+                self.write_statement()
+            } else if &x == "read" {
+                self.read_statement()
             } else {
-                panic!("Unimplemented keyword!")
+                // Only 'else', 'elif' and 'in' are left, and those can only follow
+                //   another part of the statement they belong to.
+                Err(self.lexer.unexpected(Vec::new()))
             },
             
             // This node is added to the AST so we can also handle intentionally empty statements.
-            Token::Operator(Op::LineEnd) => Statement::Scope(Scope::default()),
-            
-            // // An empty block.
-            // Token::Operator(Op::BlockEnd) => if self.lexer.peek_back() == '{' {
-            //     Statement::Scope(Scope::default())
-            // } ,
+            Token::Operator(Op::LineEnd) => {
+                self.lexer.eat(Token::Operator(Op::LineEnd))?;
+                Ok(Statement::Scope(Scope::default()))
+            }
 
-            _ => panic!(
-                "Did not expect {} at position {}",
-                self.get_curr().get_val(),
-                self.get_curr().get_pos()
-            ),
+            _ => Err(self.lexer.unexpected(Vec::new())),
         }
     }
 
     // Disambigutes the various node types which hold a Var token
-    fn var_disambiguate(&mut self) -> Factor {
-        let m = self.lexer.peek_token();
-        let t: Factor;
+    fn var_disambiguate(&mut self) -> PResult<Factor> {
+        let m = self.lexer.peek_token()?;
 
         match m.get_val() {
             // If the next token is a left parenthesis, it can only be a function call.
-            Token::Operator(Op::LParens) => t = self.fn_call(),
+            Token::Operator(Op::LParens) => self.fn_call(),
 
             // If the next token is a right parenthesis or a comma,
             //   the variable is probably part of an arg list.
             // This will probably be depreciated.
-            Token::Operator(Op::RParens) | Token::Operator(Op::Comma) => t = self.id(),
+            Token::Operator(Op::RParens) | Token::Operator(Op::Comma) => self.id(),
 
             // Otherwise, it is a variable name.
-            _ => t = self.id(),
+            _ => self.id(),
         }
-
-        t
     }
 
     // A statement is an assign statement if it contains the assign operator.
-    fn assign_statement(&mut self) -> Statement {
-        let t = Statement::Assign {
-            left: Expr::Factor(boxe!(self.id())),
-            right: {
-                self.lexer.eat(Token::Operator(Op::Assign));
-                self.expr()
+    fn assign_statement(&mut self) -> PResult<Statement> {
+        let left = Expr::Factor(boxe!(self.id()?));
+        let t = self.assignment(left)?;
+
+        if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
+        }
+
+        Ok(t)
+    }
+
+    // The assignment operator and right hand side of an assignment to the given target.
+    //   A compound assignment like 'x += 1' keeps the operator, so the target only
+    //   has to be evaluated once.
+    fn assignment(&mut self, left: Expr) -> PResult<Statement> {
+        let m = self.get_curr();
+
+        match m.get_val() {
+            Token::Operator(Op::Assign) => {
+                self.lexer.eat(m.get_val())?;
+                Ok(Statement::Assign {
+                    left,
+                    right: self.tuple_expr()?
+                })
             }
+            Token::Operator(Op::CompoundAssign(op)) => {
+                self.lexer.eat(m.get_val())?;
+                Ok(Statement::CompoundAssign {
+                    left,
+                    op: *op,
+                    right: self.tuple_expr()?
+                })
+            }
+            _ => Err(self.lexer.unexpected(vec![Token::Operator(Op::Assign)])),
+        }
+    }
+
+    // An expression on its own. Its value is discarded, except at the REPL where it is printed.
+    //
+    // If an index expression is followed by an assignment operator, as in 'a[i] = x'
+    //   or 'a[i] += 1', this is an assignment to an element instead.
+    fn expr_statement(&mut self) -> PResult<Statement> {
+        let e = self.tuple_expr()?;
+
+        let t = match (e, self.get_curr().get_val()) {
+            (e @ Expr::Index { .. }, Token::Operator(Op::Assign))
+          | (e @ Expr::Index { .. }, Token::Operator(Op::CompoundAssign(_))) => self.assignment(e)?,
+            // Assigning to several targets, as in 'a, b = b, a'.
+            (e, Token::Operator(Op::Assign)) => match e {
+                Expr::Factor(ref f) if is_tuple(f) => self.assignment(e.clone())?,
+                _ => Statement::Expr(e),
+            },
+            (e, _) => Statement::Expr(e),
+        };
+
+        // The expression has to end here, otherwise something like '2 3' would silently become two statements.
+        match self.get_curr().get_val() {
+            Token::Operator(Op::LineEnd) => {
+                self.lexer.eat(Token::Operator(Op::LineEnd))?;
+            }
+            Token::Operator(Op::BlockEnd) | Token::None => {}
+            _ => return Err(self.lexer.unexpected(vec![Token::Operator(Op::LineEnd)])),
+        }
+
+        Ok(t)
+    }
+
+    // Declares an array, as in 'array a = [1, 2, 3]'. The value can also be a length,
+    //   so 'array a = 10' is ten zeros, and leaving it out gives an empty array.
+    fn array_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["array"].clone())?;
+
+        let name = self.lexer.eat(Token::Var(String::new()))?;
+        let value = if self.get_curr().get_val() == Token::Operator(Op::Assign) {
+            self.lexer.eat(Token::Operator(Op::Assign))?;
+            Some(self.expr()?)
+        } else {
+            None
         };
 
         if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
-            self.lexer.eat(Token::Operator(Op::LineEnd));
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
         }
 
-        t
+        Ok(Statement::ArrayDecl {
+            name,
+            value
+        })
+    }
+
+    // Declares a state variable, as in 'state rate = 0.0725'. Without a value,
+    //   an existing global variable becomes a state variable.
+    fn state_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["state"].clone())?;
+
+        let name = self.lexer.eat(Token::Var(String::new()))?;
+        let value = if self.get_curr().get_val() == Token::Operator(Op::Assign) {
+            self.lexer.eat(Token::Operator(Op::Assign))?;
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
+        }
+
+        Ok(Statement::State {
+            name,
+            value
+        })
     }
 
     // A return statement. It returns the value of the nested statement.
-    fn return_statement(&mut self) -> Statement {
-        self.lexer.eat(KEYWORD_TABLE["return"].clone());
+    fn return_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["return"].clone())?;
         let t = Statement::Return{
-            val: self.expr()
+            val: self.tuple_expr()?
         };
 
         if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
-            self.lexer.eat(Token::Operator(Op::LineEnd));
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
         }
 
-        t
+        Ok(t)
     }
 
-    fn conditional_statement(&mut self) -> Statement {
-        self.lexer.eat(KEYWORD_TABLE["if"].clone());
-        Statement::Branch {
-            if_block: CondBlock{cond: self.relational_expr(), body: boxe!(self.statement())},
+    fn conditional_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["if"].clone())?;
+        Ok(Statement::Branch {
+            if_block: CondBlock{cond: self.expr()?, body: boxe!(self.statement()?)},
             alt_blocks: {
                 let mut x: Vec<CondBlock> = Vec::new();
                 
@@ -1030,8 +2169,8 @@ impl Parser {
                     Token::KeyWord(x) => &x == "elif",
                     _ => false,
                 } {
-                    self.lexer.eat(KEYWORD_TABLE["elif"].clone());
-                    x.push(CondBlock{cond: self.expr(), body:boxe!(self.statement())});
+                    self.lexer.eat(KEYWORD_TABLE["elif"].clone())?;
+                    x.push(CondBlock{cond: self.expr()?, body:boxe!(self.statement()?)});
 
                 }
 
@@ -1042,210 +2181,160 @@ impl Parser {
                         Token::KeyWord(x) => &x == "else",
                     _ => false,
                 } {
-                    self.lexer.eat(KEYWORD_TABLE["else"].clone());
-                    Some(boxe!(self.statement()))
+                    self.lexer.eat(KEYWORD_TABLE["else"].clone())?;
+                    Some(boxe!(self.statement()?))
                 } else {
                     None
                 }
             }
-        }
+        })
     }
 
-    fn get_curr(&self) -> TokStruct {
-        self.lexer.get_curr()
-    }
+    // A while loop, which runs its body for as long as the condition holds.
+    fn while_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["while"].clone())?;
 
-    //a == b
+        Ok(Statement::While(CondBlock {
+            cond: self.expr()?,
+            body: boxe!(self.loop_body()?)
+        }))
+    }
 
-    pub fn bool_expr(&mut self) -> Expr {
-        let mut t = self.bool_term();
-        let mut m = self.get_curr();
+    // A for loop, as in 'for x in range(10) { ... }'.
+    fn for_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["for"].clone())?;
 
-        while match self.get_curr().get_val() {
-            Token::Operator(Op::Or_) => true,
-            _ => false,
-        } {
-            t = Expr::Binary {
-                left: boxe!(t),
-                right: boxe!(self.bool_term()),
-                op: Op::Or_
+        let var = self.lexer.eat(Token::Var(String::new()))?;
+        let target = if self.get_curr().get_val() == Token::Operator(Op::Comma) {
+            let mut t = TupleLiteral {
+                start: var.clone(),
+                items: vec![Expr::Factor(boxe!(Factor::Symbol(var)))],
             };
-            m = self.get_curr();
-        }
 
-        t
+            while self.get_curr().get_val() == Token::Operator(Op::Comma) {
+                self.lexer.eat(Token::Operator(Op::Comma))?;
+                let v = self.lexer.eat(Token::Var(String::new()))?;
+                t.items.push(Expr::Factor(boxe!(Factor::Symbol(v))));
+            }
+
+            Expr::Factor(boxe!(Factor::Tuple(t)))
+        } else {
+            Expr::Factor(boxe!(Factor::Symbol(var)))
+        };
+        self.lexer.eat(KEYWORD_TABLE["in"].clone())?;
+
+        Ok(Statement::For {
+            target,
+            iter: self.expr()?,
+            body: boxe!(self.loop_body()?)
+        })
     }
 
-    pub fn bool_term(&mut self) -> Expr {
-        let mut t = self.bool_factor();
-        let mut m = self.get_curr();
+    fn loop_body(&mut self) -> PResult<Statement> {
+        self.loops += 1;
+        let body = self.statement();
+        self.loops -= 1;
 
-        while match self.get_curr().get_val() {
-            Token::Operator(Op::And) => true,
-            _ => false,
-        } {
-            t = Expr::Binary {
-                left: boxe!(t),
-                right: boxe!(self.bool_factor()),
-                op: Op::And
-            };
-            m = self.get_curr();
+        body
+    }
+
+    // 'break' or 'continue', which can only appear inside a loop.
+    fn loop_control_statement(&mut self, keyword: &str) -> PResult<Statement> {
+        if self.loops == 0 {
+            return Err(ParseError::new(
+                ParseErrorKind::OutsideLoop,
+                self.lexer.curr_span(),
+                Vec::new(),
+                self.get_curr().get_val(),
+            ));
         }
 
-        t
-    }
+        let t = self.lexer.eat(KEYWORD_TABLE[keyword].clone())?;
 
-    pub fn bool_factor(&mut self) -> Expr {
-        let t: Expr;
-        let m = self.get_curr();
-        match self.get_curr().get_val() {
-            Token::Bool(_) | Token::Var(_) | Token::Number(_) | Token::Operator(Op::LParens) => {
-                t = self.relational_expr();
-            }
-            Token::Operator(Op::Not) => {
-                t = Expr::Unary {
-                    right: boxe!(self.bool_factor()),
-                    op: Op::Not
-                };
-            }
-            _ => panic!(
-                "bool_factor: Expected Number or Variable at position {}, got {}!",
-                self.get_curr().get_pos(),
-                self.get_curr().get_val()
-            ),
+        if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
         }
 
-        t
+        Ok(if keyword == "break" {
+            Statement::Break(t)
+        } else {
+            Statement::Continue(t)
+        })
     }
 
-    fn relational_expr(&mut self) -> Expr {
-        let mut t = self.relational_factor();
+    // Prints one or more values, as in 'write "x is", x'.
+    fn write_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["write"].clone())?;
 
-        while match self.get_curr().get_val() {
-            Token::Operator(Op::Eq_)
-            | Token::Operator(Op::Neq)
-            | Token::Operator(Op::Lt_)
-            | Token::Operator(Op::Leq)
-            | Token::Operator(Op::Gt_)
-            | Token::Operator(Op::Geq) => true,
-            _ => false,
-        } {
-            match self.get_curr().get_val() {
-                Token::Operator(Op::Eq_) | Token::Operator(Op::Neq) => {
-                    let m = self.get_curr();
-                    t = Expr::Binary {
-                        left: boxe!(t),
-                        op: self.lexer.eat(m.get_val()).get_val().unwrap_as_op().unwrap(),
-                        right: boxe!(self.relational_factor()),
-                    }
-                }
-                Token::Operator(Op::Lt_)
-              | Token::Operator(Op::Leq)
-              | Token::Operator(Op::Gt_)
-              | Token::Operator(Op::Geq) => {
-                    let m = self.get_curr();
-                    t = Expr::Binary {
-                        left: boxe!(t),
-                        op: self.lexer.eat(m.get_val()).get_val().unwrap_as_op().unwrap(),
-                        right: boxe!(self.relational_factor()),
-                    }
-                }
-                Token::Operator(Op::And) | Token::Operator(Op::Or_) => {
-                    let m = self.get_curr();
-                    t = Expr::Binary {
-                        left: boxe!(t),
-                        op: self.lexer.eat(m.get_val()).get_val().unwrap_as_op().unwrap(),
-                        right: boxe!(self.relational_factor()),
-                    }
-                }
-                Token::None => {}
-                _ => panic!(
-                    "relational_expr: Expected Relational operator at position {}, got {}!",
-                    self.get_curr().get_pos(),
-                    self.get_curr().get_val() /* !(a > (b - c)) */
-                ),
-            }
+        let mut values = vec![self.expr()?];
+        while self.get_curr().get_val() == Token::Operator(Op::Comma) {
+            self.lexer.eat(Token::Operator(Op::Comma))?;
+            values.push(self.expr()?);
         }
 
-        t
+        if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
+        }
+
+        Ok(Statement::Write(values))
     }
 
-    fn relational_factor(&mut self) -> Expr {
-        let t: Expr;
-        let m = self.lexer.get_curr();
+    // Reads a value typed in by the user into a variable, as in 'read x'.
+    fn read_statement(&mut self) -> PResult<Statement> {
+        self.lexer.eat(KEYWORD_TABLE["read"].clone())?;
+        let t = self.lexer.eat(Token::Var(String::new()))?;
 
-        match m.get_val() {
-            Token::Number(_) => t = self.expr(),
-            Token::Var(_) => {
-                t = Expr::Factor(boxe!(self.var_disambiguate()));
-            }
-            Token::Bool(_) => t = Expr::Factor(boxe!(self.boolean())),
-            Token::Operator(Op::Not) => {
-                t = Expr::Unary {
-                    right: boxe!(self.relational_expr()),
-                    op: self.lexer.eat(m.get_val()).get_val().unwrap_as_op().unwrap()
-                };
-            }
-            Token::Operator(Op::LParens) => {
-                self.lexer.eat(Token::Operator(Op::LParens));
-                t = self.relational_expr();
-                self.lexer.eat(Token::Operator(Op::RParens));
-            }
-            _ => panic!(
-                "relational_factor: Expected Value type at position {}, got {}!",
-                m.get_pos(),
-                m.get_val()
-            ),
+        if self.get_curr().get_val() == Token::Operator(Op::LineEnd) {
+            self.lexer.eat(Token::Operator(Op::LineEnd))?;
         }
-        t
+
+        Ok(Statement::Read(t))
     }
 
-    fn fn_call(&mut self) -> Factor {
-        let mut t = Factor::FnCall(
-            FnCall {
-                name: self.lexer.eat(Token::Var("".to_owned())),
-                args: ArgList::default()
-            }
-        );
+    fn get_curr(&self) -> TokStruct {
+        self.lexer.get_curr()
+    }
 
-        self.lexer.eat(Token::Operator(Op::LParens));
-        loop {
-            match self.get_curr().get_val() {
-                Token::Operator(Op::LParens) => t.as_fn_call().unwrap().args.argv.push(self.expr()),
-                Token::Var(_) => t.as_fn_call().unwrap().args.argv.push(Expr::Factor(boxe!(self.var_disambiguate()))),
-                Token::Number(_) => t.as_fn_call().unwrap().args.argv.push(Expr::Factor(boxe!(self.number()))),
-                Token::Operator(Op::RParens) => break,
-                _ => panic!(
-                    "fn_call: Did not expect {} at position {} in function call args!",
-                    self.get_curr().get_val(),
-                    self.get_curr().get_pos()
-                ),
-            };
-            match self.get_curr().get_val() {
-                Token::Operator(Op::Comma) => {
-                    self.lexer.eat(Token::Operator(Op::Comma));
-                }
-                Token::Operator(Op::RParens) => break,
-                _ => panic!("herpaderp!"),
+    fn fn_call(&mut self) -> PResult<Factor> {
+        Ok(Factor::FnCall(
+            FnCall {
+                name: self.lexer.eat(Token::Var("".to_owned()))?,
+                args: self.arg_list()?
             }
-        }
-        self.lexer.eat(Token::Operator(Op::RParens));
-
-        t
+        ))
     }
 
+    // A function that fails to parse is left out of the program, and parsing
+    //   resumes at the next 'fn' keyword.
     pub fn program(&mut self) -> Program {
         let mut t = Program {
             content: Vec::new()
         };
 
-        t.content.push(self.function());
+        loop {
+            match self.get_curr().get_val() {
+                Token::None => break,
+
+                // Blank lines between functions.
+                Token::Operator(Op::LineEnd) => self.advance(),
+
+                _ => {
+                    let start = self.get_curr().get_pos();
 
-        while match self.get_curr().get_val() {
-            Token::None | Token::Operator(Op::LineEnd) => false,
-            _ => true,
-        } {
-            t.content.push(self.function());
+                    match self.function() {
+                        Ok(f) => t.content.push(f),
+                        Err(e) => {
+                            self.report(e);
+                            self.recover(start);
+
+                            // A stray block end cannot start anything, so skip it to avoid getting stuck.
+                            if self.get_curr().get_val() == Token::Operator(Op::BlockEnd) {
+                                self.advance();
+                            }
+                        }
+                    }
+                }
+            }
         }
 
         t
@@ -1254,7 +2343,9 @@ impl Parser {
 
 /* 
 
-    program: (function)+
+    program: (function | LINE_END)*
+
+    repl_line: (statement)*
 
     function: function_name LPARENS args RPARENS scope
 
@@ -1264,35 +2355,249 @@ impl Parser {
 
     statement_list: statement LINE_END statement_list
 
-    statement: expr | scope | assign_statement | return_statement | conditional_statement
+    statement: expr_statement | scope | assign_statement | array_statement | state_statement | return_statement | conditional_statement
+             | while_statement | for_statement | BREAK (LINE_END)? | CONTINUE (LINE_END)?
+             | write_statement | read_statement | function
+
+    expr_statement: tuple_expr ((ASSIGN | COMPOUND_ASSIGN) tuple_expr)? (LINE_END)?
+
+    array_statement: ARRAY VARIABLE (ASSIGN expr)? (LINE_END)?
+
+    state_statement: STATE VARIABLE (ASSIGN expr)? (LINE_END)?
+
+    while_statement: WHILE expr statement
 
-    conditional_statement: IF bool_expr scope (ELSE IF bool_expr scope)* (ELSE scope)?
+    for_statement: FOR VARIABLE (COMMA VARIABLE)* IN expr statement
 
-    return_statement: RETURN expr ()
+    write_statement: WRITE expr (COMMA expr)* (LINE_END)?
 
-    assign_statement: VARIABLE ASSIGN (expr | bool_expr)
+    read_statement: READ VARIABLE (LINE_END)?
 
-    fn_call: function_name LPARENS expr RPARENS
+    conditional_statement: IF expr statement (ELIF expr statement)* (ELSE statement)?
 
-    args: arg (SEPARATOR arg)*
+    return_statement: RETURN tuple_expr (LINE_END)?
 
-    arg: (QUALIFIER)? VARIABLE
+    assign_statement: VARIABLE (ASSIGN | COMPOUND_ASSIGN) tuple_expr (LINE_END)?
 
-    bool_expr: bool_term (OR bool_term)*
+        COMPOUND_ASSIGN is any of '+=', '-=', '*=', '/=', '//=', '%=', '**=', '&=', '|=', '^=', '<<=', '>>=' and '>>>='.
 
-    bool_term: bool_factor (AND bool_factor)*
+    fn_call: function_name LPARENS (call_arg (COMMA call_arg)*)? RPARENS
 
-    bool_factor: (NOT)? BOOL | VARIABLE | bool_expr | fn_call | relational_expr
+    call_arg: (VARIABLE ASSIGN)? expr
 
-    relational_expr: relational_factor ((LT | GT | LEQ | GEQ | EQ | NEQ) relational_factor)*
+    args: param (SEPARATOR param)* (SEPARATOR MUL VARIABLE)? | MUL VARIABLE
 
-    relational_factor: VARIABLE | NUMBER | BOOL
+    param: VARIABLE (ASSIGN expr)?
 
-    expr: term ((ADD | SUB) term)*
+        Once a parameter has a default value, every parameter after it needs one too.
 
-    term: pow_factor ((MUL | DIV | MOD) pow_factor)*
+    tuple_expr: expr (COMMA expr)*
 
-    pow_factor: factor (POW factor)
+    expr: binary_expr(0) (IF binary_expr(0) ELSE expr)?
 
-    factor: NUMBER | VARIABLE | fn_call | LPARENS expr RPARENS | POW pow_factor | (POS | NEG) factor
+    binary_expr(min): unary (OP binary_expr(precedence(OP) + 1))*   where precedence(OP) >= min
+
+        OP, loosest first:  OR  |  AND  |  EQ NEQ LT GT LEQ GEQ  |  BIT_OR  |  BIT_XOR  |  BIT_AND
+                            |  LLS ARS LRS  |  ADD SUB  |  MUL DIV INTDIV MOD
+
+        A chain of comparisons such as 'a < b <= c' becomes one comparison_chain node.
+
+    comparison_chain: binary_expr(precedence(EQ) + 1) ((EQ | NEQ | LT | GT | LEQ | GEQ) binary_expr(precedence(EQ) + 1))+
+
+    unary: NOT binary_expr(precedence(EQ)) | (POS | NEG | BIT_NOT) unary | power
+
+    power: factor (POW unary)?
+
+    factor: (NUMBER | STRING | BOOL | VARIABLE | fn_call | array_literal | map_literal | lambda | parenthesized) postfix
+
+    parenthesized: LPARENS (expr | (expr COMMA)+ (expr)?)? RPARENS
+
+    array_literal: LBRACKET (expr (COMMA expr)* (COMMA)?)? RBRACKET
+
+    map_literal: BLOCK_START (expr COLON expr (COMMA expr COLON expr)* (COMMA)?)? BLOCK_END
+
+    lambda: BIT_OR (VARIABLE (COMMA VARIABLE)*)? BIT_OR expr | OR expr | FN args (ARROW expr | scope)
+
+    postfix: (LBRACKET (expr | (expr)? COLON (expr)? (COLON (expr)?)?) RBRACKET | LPARENS (expr (COMMA expr)*)? RPARENS)*
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses one REPL line and gives the kinds of errors found, if any. Like the REPL,
+    //   it runs on a thread with the interpreter's stack.
+    fn errors(src: &str) -> Vec<ParseErrorKind> {
+        let src = src.to_owned();
+
+        std::thread::Builder::new()
+            .stack_size(::interpreter::STACK_SIZE)
+            .spawn(move || {
+                let mut parser = Parser::new();
+                parser.input(src);
+
+                match parser.parse_repl_line() {
+                    Ok(_) => Vec::new(),
+                    Err(e) => e.into_iter().map(|e| e.kind).collect(),
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn empty_lines_parse() {
+        assert_eq!(errors(""), vec![]);
+        assert_eq!(errors("   "), vec![]);
+        assert_eq!(errors("# just a comment"), vec![]);
+    }
+
+    #[test]
+    fn braces_open_maps_or_blocks() {
+        let kinds = |src: &str| -> Vec<String> {
+            let mut parser = Parser::new();
+            parser.input(src.to_owned());
+
+            parser.parse_repl_line().unwrap().iter().map(|s| match s {
+                Statement::Scope(_) => "block".to_owned(),
+                Statement::Expr(_) => "map".to_owned(),
+                s => format!("{:?}", s),
+            }).collect()
+        };
+
+        assert_eq!(kinds("{\"a\": 1}"), vec!["map"]);
+        assert_eq!(kinds("{\"a\" + \"b\": 1, -2: 3}"), vec!["map"]);
+        assert_eq!(kinds("{f(1)[0]: 1}"), vec!["map"]);
+        assert_eq!(kinds("{x = 1; y = 2;}"), vec!["block"]);
+        assert_eq!(kinds("{f(1); }"), vec!["block"]);
+        assert_eq!(kinds("{}"), vec!["block"]);
+    }
+
+    #[test]
+    fn prefixed_integers_and_names_lex_apart() {
+        let mut lexer = Lexer::new();
+        lexer.input("0x1F 0o17 0b1010 1_000 abs bad 0xbad".to_owned());
+        let toks = lexer.get_all().unwrap();
+
+        let int = |x: u32| Token::Int(BigInt::from(x));
+        let var = |s: &str| Token::Var(s.to_owned());
+        assert_eq!(toks, vec![int(31), int(15), int(10), int(1000), var("abs"), var("bad"), int(0xbad)]);
+    }
+
+    #[test]
+    fn integers_are_read_in_the_lexer_base() {
+        let int = |x: u32| Token::Int(BigInt::from(x));
+        let var = |s: &str| Token::Var(s.to_owned());
+
+        let mut lexer = Lexer::new();
+        lexer.base(16).input("10 0ff ff 1e3 0b1 0x1F 0o17 1_f".to_owned());
+        assert_eq!(
+            lexer.get_all().unwrap(),
+            vec![int(16), int(255), var("ff"), int(0x1e3), int(0xb1), int(31), int(15), int(31)]
+        );
+
+        let mut lexer = Lexer::new();
+        lexer.base(2).input("101 0x1F".to_owned());
+        assert_eq!(lexer.get_all().unwrap(), vec![int(5), int(31)]);
+
+        let mut lexer = Lexer::new();
+        lexer.base(2).input("12".to_owned());
+        assert!(lexer.get_all().is_err());
+    }
+
+    #[test]
+    fn lexer_errors_have_their_own_kinds() {
+        assert_eq!(errors("1.2.3"), vec![ParseErrorKind::InvalidNumber]);
+        assert_eq!(errors("\"abc"), vec![ParseErrorKind::UnterminatedString]);
+        assert_eq!(errors("\"a\\q\""), vec![ParseErrorKind::InvalidEscape]);
+        assert_eq!(errors("1 $ 2"), vec![ParseErrorKind::UnknownSymbol]);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_eq!(errors("x = 1 + /* two\nlines */ 2 # and the rest"), vec![]);
+        assert_eq!(errors("x = 4 // 2 # '//' divides"), vec![]);
+        assert_eq!(errors("x = 1 /* never closed"), vec![ParseErrorKind::UnterminatedComment]);
+    }
+
+    #[test]
+    fn stray_keywords_are_unexpected() {
+        for src in &["else { x = 1; }", "elif x { }", "in [1]", "x = 1; else x = 2"] {
+            assert_eq!(errors(src), vec![ParseErrorKind::UnexpectedToken], "{}", src);
+        }
+    }
+
+    #[test]
+    fn positional_arguments_come_before_keywords() {
+        assert_eq!(errors("f(1, a=2)"), vec![]);
+        assert_eq!(errors("f(a=1, 2)"), vec![ParseErrorKind::PositionalAfterKeyword]);
+        assert_eq!(errors("f(1, a=2, b, c=3)"), vec![ParseErrorKind::PositionalAfterKeyword]);
+    }
+
+    #[test]
+    fn loop_keywords_need_a_loop() {
+        assert_eq!(errors("break"), vec![ParseErrorKind::OutsideLoop]);
+        assert_eq!(errors("fn f() { continue; }"), vec![ParseErrorKind::OutsideLoop]);
+        assert_eq!(errors("while true { if 1 { break; } }"), vec![]);
+    }
+
+    #[test]
+    fn parsing_carries_on_after_an_error() {
+        assert_eq!(
+            errors("x = 1 +; y = 2 *; z = 3"),
+            vec![ParseErrorKind::UnexpectedToken, ParseErrorKind::UnexpectedToken]
+        );
+    }
+
+    #[test]
+    fn statements_are_told_apart() {
+        let mut parser = Parser::new();
+        parser.input("x = 1; x += 2; fn f(a, b = 2) { return a; } state s = 1; f(x)".to_owned());
+
+        let kinds: Vec<&str> = parser
+            .parse_repl_line()
+            .unwrap()
+            .iter()
+            .map(|s| match s {
+                Statement::Assign { .. } => "assign",
+                Statement::CompoundAssign { .. } => "compound",
+                Statement::FnDecl(_) => "fn",
+                Statement::State { .. } => "state",
+                Statement::Expr(_) => "expr",
+                _ => "other",
+            })
+            .collect();
+
+        assert_eq!(kinds, vec!["assign", "compound", "fn", "state", "expr"]);
+    }
+
+    #[test]
+    fn bad_functions_do_not_hang() {
+        assert_eq!(errors("fn $"), vec![ParseErrorKind::UnknownSymbol]);
+        assert_eq!(errors("fn @"), vec![ParseErrorKind::UnknownSymbol]);
+        assert_eq!(errors("fn f() { fn $ }"), vec![ParseErrorKind::UnknownSymbol]);
+
+        let mut parser = Parser::new();
+        parser.input("fn $\nfn main() { return 1; }".to_owned());
+        assert!(parser.eval().is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let deep = |open: &str, close: &str, n: usize| format!("{}1{}", open.repeat(n), close.repeat(n));
+
+        assert_eq!(errors(&deep("(", ")", 100)), vec![]);
+        assert_eq!(errors(&deep("(", ")", 20000)), vec![ParseErrorKind::TooDeep]);
+        assert_eq!(errors(&deep("-", "", 200000)), vec![ParseErrorKind::TooDeep]);
+        assert_eq!(errors(&deep("[", "]", 20000)), vec![ParseErrorKind::TooDeep]);
+        assert_eq!(errors(&deep("{", "}", 20000)), vec![ParseErrorKind::TooDeep]);
+    }
+
+    #[test]
+    fn incomplete_lines_are_errors() {
+        for src in &["y = 1 +", "x =", "-", "f(1,", "(1,", "a[", "return"] {
+            assert_eq!(errors(src), vec![ParseErrorKind::UnexpectedToken], "{}", src);
+        }
+    }
+}
@@ -0,0 +1,1224 @@
+use decimal;
+use interpreter::Closure;
+use parser::Token;
+use word;
+
+use bigdecimal::{BigDecimal, Context};
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Pow, Signed, ToPrimitive, Zero};
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+// Operator overloads.
+use std::ops::*;
+
+// The result of an operation on values. Errors are plain messages; the
+//   interpreter attaches a position to them.
+pub type ValueResult = Result<Value, String>;
+
+// The keys and values of a map, in the order they were added.
+pub type MapEntries = Vec<(Value, Value)>;
+
+// A runtime value in RCScript.
+//
+// Integers are arbitrary precision, so integer arithmetic is always exact.
+//   Dividing integers gives an exact rational, which turns back into an integer
+//   whenever its denominator is 1. Exact numbers are only turned into floats
+//   when they meet a float. While a decimal precision is set, fractional
+//   literals and results are decimals of that precision instead of floats.
+//   Complex numbers are pairs of floats, and anything
+//   that meets a complex number becomes complex.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(BigInt),
+    Rational(BigRational),
+    Float(f64),
+    Decimal(BigDecimal),
+    Complex(Complex64),
+    Bool(bool),
+    Str(String),
+    // Arrays are shared, so every variable holding the same array sees changes to it.
+    Array(Rc<RefCell<Vec<Value>>>),
+    // Maps are shared like arrays. They keep their keys in the order they were added,
+    //   and are searched in order, which is quick enough for the small lookup tables they hold.
+    Map(Rc<RefCell<MapEntries>>),
+    // A fixed list of values, such as the results of a function that returns several.
+    //   Unlike arrays, tuples cannot be changed.
+    Tuple(Rc<Vec<Value>>),
+    // A function value, either a lambda or a named function used without calling it.
+    Function(Rc<Closure>),
+    None,
+}
+
+// Two numeric operands converted to a common type.
+enum Pair {
+    Ints(BigInt, BigInt),
+    Rationals(BigRational, BigRational),
+    Floats(f64, f64),
+    Decimals(BigDecimal, BigDecimal, Context),
+    Complexes(Complex64, Complex64),
+}
+
+// How exact numbers are shown. Exact mode shows rationals as fractions, decimal
+//   mode shows them as floats. Integers and floats look the same in both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayMode {
+    Exact,
+    Decimal,
+}
+
+impl Value {
+    // Converts a literal token into a value.
+    pub fn from_token(tok: Token) -> Value {
+        match tok {
+            Token::Int(x) => Value::Int(x),
+            Token::Number(x) => match decimal::context() {
+                Some(ctx) => Value::Decimal(ctx.round_decimal(x)),
+                // The decimal holds the literal exactly, so parsing its digits gives the
+                //   closest float, which converting the decimal directly does not.
+                None => Value::Float(x.to_string().parse().unwrap_or(f64::NAN)),
+            },
+            Token::Imag(x) => Value::Complex(Complex64::new(0., x)),
+            Token::Bool(x) => Value::Bool(x),
+            Token::Str(x) => Value::Str(x),
+            _ => Value::None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Rational(_) => "rational",
+            Value::Float(_) => "float",
+            Value::Decimal(_) => "decimal",
+            Value::Complex(_) => "complex",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Tuple(_) => "tuple",
+            Value::Function(_) => "function",
+            Value::None => "none",
+        }
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(x) => x.to_f64(),
+            Value::Rational(x) => x.to_f64(),
+            Value::Float(x) => Some(*x),
+            Value::Decimal(x) => x.to_f64(),
+            _ => None,
+        }
+    }
+
+    // Exact numbers and decimals as a decimal, rounded to the given context.
+    pub fn to_decimal(&self, ctx: &Context) -> Option<BigDecimal> {
+        match self {
+            Value::Int(x) => Some(ctx.round_decimal(BigDecimal::new(x.clone(), 0))),
+            Value::Rational(x) => Some(decimal::from_rational(x, ctx)),
+            Value::Decimal(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    // Any number as a complex number with a zero imaginary part, or a complex number as is.
+    pub fn to_complex(&self) -> Option<Complex64> {
+        match self {
+            Value::Complex(z) => Some(*z),
+            _ => self.to_f64().map(|x| Complex64::new(x, 0.)),
+        }
+    }
+
+    // Exact numbers as a rational. Floats are not exact, so they give None.
+    //   Decimals are, since a decimal is a fraction over a power of ten.
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::Int(x) => Some(BigRational::from_integer(x.clone())),
+            Value::Rational(x) => Some(x.clone()),
+            Value::Decimal(x) => Some(decimal::to_rational(x)),
+            _ => None,
+        }
+    }
+
+    // Wraps a rational, simplifying it to an integer if it is a whole number.
+    pub fn from_rational(x: BigRational) -> Value {
+        if x.is_integer() {
+            Value::Int(x.to_integer())
+        } else {
+            Value::Rational(x)
+        }
+    }
+
+    // Whether the value counts as true in a condition. Zero is false, as in python.
+    pub fn truthy(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Int(x) => Some(!x.is_zero()),
+            Value::Rational(x) => Some(!x.is_zero()),
+            Value::Float(x) => Some(*x != 0f64),
+            Value::Decimal(x) => Some(!x.is_zero()),
+            Value::Complex(z) => Some(!z.is_zero()),
+            Value::Str(s) => Some(!s.is_empty()),
+            Value::Array(a) => Some(!a.borrow().is_empty()),
+            Value::Map(m) => Some(!m.borrow().is_empty()),
+            Value::Tuple(t) => Some(!t.is_empty()),
+            Value::Function(_) => Some(true),
+            Value::None => None,
+        }
+    }
+
+    // Brings two numbers to a common type. Integers are promoted to rationals,
+    //   and exact numbers are promoted to floats if the other side is a float.
+    //   If either side is complex, both sides are made complex.
+    //
+    //   A decimal meeting an exact number gives decimals. A decimal meeting a float
+    //   gives floats, since the float has already lost the extra digits; so do
+    //   decimals left over after the precision has been turned off.
+    fn pair(self, other: Value, op: &str) -> Result<Pair, String> {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Ok(Pair::Ints(x, y)),
+            (l @ Value::Complex(_), r) | (l, r @ Value::Complex(_)) => match (l.to_complex(), r.to_complex()) {
+                (Some(x), Some(y)) => Ok(Pair::Complexes(x, y)),
+                _ => Err(format!(
+                    "Cannot apply {} to {} and {}",
+                    op,
+                    l.type_name(),
+                    r.type_name()
+                )),
+            },
+            (l @ Value::Decimal(_), r) | (l, r @ Value::Decimal(_)) => {
+                if let Some(ctx) = decimal::context() {
+                    if let (Some(x), Some(y)) = (l.to_decimal(&ctx), r.to_decimal(&ctx)) {
+                        return Ok(Pair::Decimals(x, y, ctx));
+                    }
+                }
+
+                Value::float_pair(l, r, op)
+            }
+            (l, r) => match (l.to_rational(), r.to_rational()) {
+                (Some(x), Some(y)) => Ok(Pair::Rationals(x, y)),
+                _ => Value::float_pair(l, r, op),
+            },
+        }
+    }
+
+    fn float_pair(l: Value, r: Value, op: &str) -> Result<Pair, String> {
+        match (l.to_f64(), r.to_f64()) {
+            (Some(x), Some(y)) => Ok(Pair::Floats(x, y)),
+            _ => Err(format!(
+                "Cannot apply {} to {} and {}",
+                op,
+                l.type_name(),
+                r.type_name()
+            )),
+        }
+    }
+
+    // Formats the value for output in the given mode, with integers in the given radix.
+    pub fn display(&self, mode: DisplayMode, radix: u32) -> String {
+        match (self, mode) {
+            (Value::Int(x), _) => int_to_radix(x, radix),
+            (Value::Rational(x), DisplayMode::Decimal) => match decimal::context() {
+                Some(ctx) => format!("{}", decimal::from_rational(x, &ctx)),
+                None => format!("{:?}", x.to_f64().unwrap_or(f64::NAN)),
+            },
+            _ => format!("{}", self),
+        }
+    }
+
+    // Floor division, '//'. Rounds towards negative infinity like python does.
+    pub fn floor_div(self, other: Value) -> ValueResult {
+        match self.pair(other, "//")? {
+            Pair::Ints(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Ints(x, y) => Ok(Value::Int(x.div_floor(&y))),
+            Pair::Rationals(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Rationals(x, y) => Ok(Value::Int((x / y).floor().to_integer())),
+            Pair::Floats(_, y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Floats(x, y) => Ok(Value::Float((x / y).floor())),
+            Pair::Decimals(_, ref y, _) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Decimals(x, y, _) => {
+                Ok(Value::Int((decimal::to_rational(&x) / decimal::to_rational(&y)).floor().to_integer()))
+            }
+            Pair::Complexes(..) => Err("Cannot apply // to complex numbers".to_owned()),
+        }
+    }
+
+    // Exponentiation, '**'. An exact number raised to an integer stays exact;
+    //   a negative exponent gives the reciprocal, so '2 ** -1' is exactly 1/2.
+    //   A negative number raised to a fraction has a complex result, so '(-8) ** (1/3)'
+    //   is the principal cube root, roughly 1+1.732j, rather than NaN.
+    pub fn pow(self, other: Value) -> ValueResult {
+        if let Some(res) = self.decimal_pow(&other) {
+            return res;
+        }
+
+        match (self.to_rational(), other) {
+            (Some(x), Value::Int(y)) => {
+                let e = match y.abs().to_u32() {
+                    Some(e) => e,
+                    None => return Err(format!("Exponent {} is too large", y)),
+                };
+
+                if y.is_negative() && x.is_zero() {
+                    return Err("Division by zero".to_owned());
+                }
+
+                let r = Pow::pow(&x, e);
+
+                Ok(Value::from_rational(if y.is_negative() { r.recip() } else { r }))
+            }
+            (_, other) => match (self.to_f64(), other.to_f64()) {
+                (Some(x), Some(y)) if x < 0f64 && y.fract() != 0f64 => {
+                    Ok(Value::Complex(Complex64::new(x, 0.).powf(y)))
+                }
+                (Some(x), Some(y)) => Ok(Value::Float(x.powf(y))),
+                _ => {
+                    // Whole powers are done by repeated multiplication, so '1j ** 2' is exactly -1.
+                    let whole = match other {
+                        Value::Int(ref y) => y.to_i32(),
+                        _ => None,
+                    };
+
+                    match (self.to_complex(), other.to_complex(), whole) {
+                        (Some(x), Some(_), Some(n)) => Ok(Value::Complex(x.powi(n))),
+                        (Some(x), Some(y), None) => Ok(Value::Complex(x.powc(y))),
+                        _ => Err(format!(
+                            "Cannot apply ** to {} and {}",
+                            self.type_name(),
+                            other.type_name()
+                        )),
+                    }
+                }
+            },
+        }
+    }
+
+    // Exponentiation where either side is a decimal, while a precision is set.
+    //   Gives None for the cases decimals cannot handle, like a negative number
+    //   to a fractional power, which are left to floats and complex numbers.
+    fn decimal_pow(&self, other: &Value) -> Option<ValueResult> {
+        match (self, other) {
+            (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {}
+            _ => return None,
+        }
+
+        let ctx = decimal::context()?;
+        let x = self.to_decimal(&ctx)?;
+        let y = other.to_decimal(&ctx)?;
+
+        if y.is_integer() {
+            let n = match y.to_i64() {
+                Some(n) => n,
+                None => return Some(Err(format!("Exponent {} is too large", y))),
+            };
+
+            if n < 0 && x.is_zero() {
+                return Some(Err("Division by zero".to_owned()));
+            }
+
+            Some(Ok(Value::Decimal(x.powi_with_context(n, &ctx))))
+        } else if x.is_zero() && y.is_positive() {
+            Some(Ok(Value::Decimal(x)))
+        } else {
+            decimal::powf(&x, &y, &ctx).map(|r| Ok(Value::Decimal(r)))
+        }
+    }
+
+    // Compares two numbers. Exact numbers compare exactly, anything involving a float compares as floats.
+    //   Complex numbers have no order, so they give None.
+    //   Strings compare alphabetically, by unicode code point.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+            (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+            _ => match (self.to_rational(), other.to_rational()) {
+                (Some(x), Some(y)) => Some(x.cmp(&y)),
+                _ => match (self.to_f64(), other.to_f64()) {
+                    (Some(x), Some(y)) => x.partial_cmp(&y),
+                    _ => None,
+                },
+            },
+        }
+    }
+
+    // Makes a new array value.
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn tuple(items: Vec<Value>) -> Value {
+        Value::Tuple(Rc::new(items))
+    }
+
+    // Splits a tuple or array into exactly n values, for assignments like 'a, b = f()'.
+    pub fn unpack(self, n: usize) -> Result<Vec<Value>, String> {
+        let items = match self {
+            Value::Tuple(t) => (*t).clone(),
+            Value::Array(a) => a.borrow().clone(),
+            v => return Err(format!("Cannot unpack {}", v.type_name())),
+        };
+
+        if items.len() == n {
+            Ok(items)
+        } else {
+            Err(format!("Expected {} values to unpack, got {}", n, items.len()))
+        }
+    }
+
+    // Makes a new map value.
+    pub fn map(entries: MapEntries) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    // Adds a key to a map, or replaces its value if it is already there.
+    pub fn map_insert(entries: &mut MapEntries, key: Value, val: Value) -> Result<(), String> {
+        if let Some(bad) = bad_key(&key) {
+            return Err(format!("Cannot use {} as a map key", bad));
+        }
+
+        match entries.iter_mut().find(|e| e.0 == key) {
+            Some(e) => e.1 = val,
+            None => entries.push((key, val)),
+        }
+
+        Ok(())
+    }
+
+    // Indexing, 'x[i]'. Negative indexes count from the end, so 's[-1]' is the last character.
+    //   Indexing a map looks up a key.
+    pub fn index(&self, i: &Value) -> ValueResult {
+        match self {
+            Value::Str(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let i = seq_index(chars.len(), i, "String")?;
+                Ok(Value::Str(chars[i].to_string()))
+            }
+            Value::Array(a) => {
+                let a = a.borrow();
+                let i = seq_index(a.len(), i, "Array")?;
+                Ok(a[i].clone())
+            }
+            Value::Tuple(t) => {
+                let i = seq_index(t.len(), i, "Tuple")?;
+                Ok(t[i].clone())
+            }
+            Value::Map(m) => m
+                .borrow()
+                .iter()
+                .find(|e| e.0 == *i)
+                .map(|e| e.1.clone())
+                .ok_or_else(|| format!("Key {} is not in the map", i.repr())),
+            v => Err(format!("Cannot index {}", v.type_name())),
+        }
+    }
+
+    // Element assignment, 'a[i] = x'. Arrays are shared, so every variable holding
+    //   this array sees the change.
+    pub fn set_index(&self, i: &Value, val: Value) -> Result<(), String> {
+        match self {
+            Value::Array(a) => {
+                let mut a = a.borrow_mut();
+                let i = seq_index(a.len(), i, "Array")?;
+                a[i] = val;
+                Ok(())
+            }
+            Value::Map(m) => Value::map_insert(&mut m.borrow_mut(), i.clone(), val),
+            Value::Str(_) => Err("Strings cannot be changed; build a new one with slices and '+'".to_owned()),
+            Value::Tuple(_) => Err("Tuples cannot be changed; use an array instead".to_owned()),
+            v => Err(format!("Cannot assign to an index of {}", v.type_name())),
+        }
+    }
+
+    // Slicing, 'x[start:end:step]'. It works like python's slices; the end is not
+    //   included, out of range bounds are cut down to fit, and a negative step goes backwards.
+    pub fn slice(&self, start: Option<&Value>, end: Option<&Value>, step: Option<&Value>) -> ValueResult {
+        match self {
+            Value::Str(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let picked = slice_indices(chars.len(), start, end, step)?;
+                Ok(Value::Str(picked.into_iter().map(|i| chars[i]).collect()))
+            }
+            // A slice of an array is a new array, not a view into the old one.
+            Value::Array(a) => {
+                let a = a.borrow();
+                let picked = slice_indices(a.len(), start, end, step)?;
+                Ok(Value::array(picked.into_iter().map(|i| a[i].clone()).collect()))
+            }
+            Value::Tuple(t) => {
+                let picked = slice_indices(t.len(), start, end, step)?;
+                Ok(Value::tuple(picked.into_iter().map(|i| t[i].clone()).collect()))
+            }
+            v => Err(format!("Cannot slice {}", v.type_name())),
+        }
+    }
+
+    // The value written the way it would be typed, so strings are quoted. Used
+    //   for the items of arrays.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(s) => format!("{:?}", s),
+            v => format!("{}", v),
+        }
+    }
+
+    // Whether 'repr' gives text that reads back as this value. Functions, none and
+    //   floats like inf and nan have no literal, and neither does anything holding them
+    //   or an array or map that holds itself.
+    pub fn is_writable(&self) -> bool {
+        self.writable_within(&mut Vec::new())
+    }
+
+    // Whether the value is writable, with 'seen' holding the arrays and maps around it.
+    fn writable_within(&self, seen: &mut Vec<*const ()>) -> bool {
+        match self {
+            Value::Float(x) => x.is_finite(),
+            Value::Complex(z) => z.re.is_finite() && z.im.is_finite(),
+            Value::Array(a) => {
+                let ptr = Rc::as_ptr(a) as *const ();
+
+                if seen.contains(&ptr) {
+                    return false;
+                }
+                seen.push(ptr);
+                let writable = a.borrow().iter().all(|v| v.writable_within(seen));
+                seen.pop();
+                writable
+            }
+            Value::Map(m) => {
+                let ptr = Rc::as_ptr(m) as *const ();
+
+                if seen.contains(&ptr) {
+                    return false;
+                }
+                seen.push(ptr);
+                let writable = m.borrow().iter().all(|e| e.0.writable_within(seen) && e.1.writable_within(seen));
+                seen.pop();
+                writable
+            }
+            Value::Tuple(t) => t.iter().all(|v| v.writable_within(seen)),
+            Value::Function(_) | Value::None => false,
+            _ => true,
+        }
+    }
+
+    // Converts a float to an integer, for things like floor() and int().
+    pub fn int_from_f64(x: f64) -> ValueResult {
+        BigInt::from_f64(x)
+            .map(Value::Int)
+            .ok_or_else(|| format!("Cannot convert {:?} to an integer", x))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other, &mut Vec::new())
+    }
+}
+
+// Whether two lists of values are equal item by item.
+fn all_equal(x: &[Value], y: &[Value], seen: &mut Vec<(*const (), *const ())>) -> bool {
+    x.len() == y.len() && x.iter().zip(y).all(|(a, b)| a.equals(b, seen))
+}
+
+impl Value {
+    // Compares two values, with 'seen' holding the pairs of arrays and maps being
+    //   compared around them. Each is equal to itself, and a pair met again inside
+    //   itself is taken to be equal, so values that hold themselves compare without looping.
+    fn equals(&self, other: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            (Value::None, Value::None) => true,
+            (Value::Str(x), Value::Str(y)) => x == y,
+            (Value::Array(x), Value::Array(y)) => {
+                let pair = (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ());
+
+                if Rc::ptr_eq(x, y) || seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let equal = all_equal(&x.borrow(), &y.borrow(), seen);
+                seen.pop();
+                equal
+            }
+            (Value::Tuple(x), Value::Tuple(y)) => all_equal(x, y, seen),
+            // Maps are equal if they have the same keys and values, in any order.
+            (Value::Map(x), Value::Map(y)) => {
+                let pair = (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ());
+
+                if Rc::ptr_eq(x, y) || seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter().all(|e| y.iter().any(|f| e.0.equals(&f.0, seen) && e.1.equals(&f.1, seen)));
+                seen.pop();
+                equal
+            }
+            // Functions are only equal to themselves.
+            (Value::Function(x), Value::Function(y)) => Rc::ptr_eq(x, y),
+            (Value::Complex(_), _) | (_, Value::Complex(_)) => match (self.to_complex(), other.to_complex()) {
+                (Some(x), Some(y)) => x == y,
+                _ => false,
+            },
+            _ => self.compare(other) == Some(Ordering::Equal),
+        }
+    }
+}
+
+// Adding strings or arrays joins them together. Joining arrays makes a new one.
+impl Add for Value {
+    type Output = ValueResult;
+
+    fn add(self, other: Value) -> ValueResult {
+        match (&self, &other) {
+            (Value::Str(x), Value::Str(y)) => return Ok(Value::Str(format!("{}{}", x, y))),
+            (Value::Array(x), Value::Array(y)) => {
+                let mut items = x.borrow().clone();
+                items.extend(y.borrow().iter().cloned());
+                return Ok(Value::array(items));
+            }
+            _ => {}
+        }
+
+        Ok(match self.pair(other, "+")? {
+            Pair::Ints(x, y) => Value::Int(x + y),
+            Pair::Rationals(x, y) => Value::from_rational(x + y),
+            Pair::Floats(x, y) => Value::Float(x + y),
+            Pair::Decimals(x, y, ctx) => Value::Decimal(ctx.round_decimal(x + y)),
+            Pair::Complexes(x, y) => Value::Complex(x + y),
+        })
+    }
+}
+
+impl Sub for Value {
+    type Output = ValueResult;
+
+    fn sub(self, other: Value) -> ValueResult {
+        Ok(match self.pair(other, "-")? {
+            Pair::Ints(x, y) => Value::Int(x - y),
+            Pair::Rationals(x, y) => Value::from_rational(x - y),
+            Pair::Floats(x, y) => Value::Float(x - y),
+            Pair::Decimals(x, y, ctx) => Value::Decimal(ctx.round_decimal(x - y)),
+            Pair::Complexes(x, y) => Value::Complex(x - y),
+        })
+    }
+}
+
+// Multiplying a string or an array by an integer repeats it, as in python.
+impl Mul for Value {
+    type Output = ValueResult;
+
+    fn mul(self, other: Value) -> ValueResult {
+        match (&self, &other) {
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                return repeat_count(n, s.len()).map(|n| Value::Str(s.repeat(n)));
+            }
+            (Value::Array(a), Value::Int(n)) | (Value::Int(n), Value::Array(a)) => {
+                let a = a.borrow();
+                let n = repeat_count(n, a.len())?;
+                return Ok(Value::array(a.iter().cycle().take(a.len() * n).cloned().collect()));
+            }
+            _ => {}
+        }
+
+        Ok(match self.pair(other, "*")? {
+            Pair::Ints(x, y) => Value::Int(x * y),
+            Pair::Rationals(x, y) => Value::from_rational(x * y),
+            Pair::Floats(x, y) => Value::Float(x * y),
+            Pair::Decimals(x, y, ctx) => Value::Decimal(ctx.round_decimal(x * y)),
+            Pair::Complexes(x, y) => Value::Complex(x * y),
+        })
+    }
+}
+
+// True division. Dividing exact numbers gives an exact result, so '1 / 3' is a rational.
+impl Div for Value {
+    type Output = ValueResult;
+
+    fn div(self, other: Value) -> ValueResult {
+        match self.pair(other, "/")? {
+            Pair::Ints(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Ints(x, y) => Ok(Value::from_rational(BigRational::new(x, y))),
+            Pair::Rationals(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Rationals(x, y) => Ok(Value::from_rational(x / y)),
+            Pair::Floats(_, y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Floats(x, y) => Ok(Value::Float(x / y)),
+            Pair::Decimals(_, ref y, _) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Decimals(x, y, ctx) => Ok(Value::Decimal(decimal::div(&x, &y, &ctx))),
+            Pair::Complexes(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Complexes(x, y) => Ok(Value::Complex(x / y)),
+        }
+    }
+}
+
+// The remainder takes the sign of the divisor, so that (a // b) * b + a % b == a.
+impl Rem for Value {
+    type Output = ValueResult;
+
+    fn rem(self, other: Value) -> ValueResult {
+        match self.pair(other, "%")? {
+            Pair::Ints(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Ints(x, y) => Ok(Value::Int(x.mod_floor(&y))),
+            Pair::Rationals(_, ref y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Rationals(x, y) => {
+                let q = (&x / &y).floor();
+                Ok(Value::from_rational(x - y * q))
+            }
+            Pair::Floats(_, y) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Floats(x, y) => Ok(Value::Float(x - y * (x / y).floor())),
+            Pair::Decimals(_, ref y, _) if y.is_zero() => Err("Division by zero".to_owned()),
+            Pair::Decimals(x, y, ctx) => {
+                let q = (decimal::to_rational(&x) / decimal::to_rational(&y)).floor();
+                Ok(Value::Decimal(ctx.round_decimal(&x - y * BigDecimal::new(q.to_integer(), 0))))
+            }
+            Pair::Complexes(..) => Err("Cannot apply % to complex numbers".to_owned()),
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = ValueResult;
+
+    fn neg(self) -> ValueResult {
+        match self {
+            Value::Int(x) => Ok(Value::Int(-x)),
+            Value::Rational(x) => Ok(Value::Rational(-x)),
+            Value::Float(x) => Ok(Value::Float(-x)),
+            Value::Decimal(x) => Ok(Value::Decimal(-x)),
+            Value::Complex(z) => Ok(Value::Complex(-z)),
+            v => Err(format!("Cannot negate {}", v.type_name())),
+        }
+    }
+}
+
+// The bitwise operators work on integers, and '&', '|' and '^' also work on booleans.
+//   Negative integers behave as if they had infinitely many leading ones, as in python.
+impl BitAnd for Value {
+    type Output = ValueResult;
+
+    fn bitand(self, other: Value) -> ValueResult {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x & y)),
+            (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(x & y)),
+            (l, r) => Err(format!("Cannot apply & to {} and {}", l.type_name(), r.type_name())),
+        }
+    }
+}
+
+impl BitOr for Value {
+    type Output = ValueResult;
+
+    fn bitor(self, other: Value) -> ValueResult {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x | y)),
+            (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(x | y)),
+            (l, r) => Err(format!("Cannot apply | to {} and {}", l.type_name(), r.type_name())),
+        }
+    }
+}
+
+impl BitXor for Value {
+    type Output = ValueResult;
+
+    fn bitxor(self, other: Value) -> ValueResult {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x ^ y)),
+            (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(x ^ y)),
+            (l, r) => Err(format!("Cannot apply ^ to {} and {}", l.type_name(), r.type_name())),
+        }
+    }
+}
+
+// Left shift, '<<'.
+impl Shl for Value {
+    type Output = ValueResult;
+
+    fn shl(self, other: Value) -> ValueResult {
+        let (x, n) = shift_operands(self, other, "<<")?;
+        Ok(Value::Int(x << n))
+    }
+}
+
+// Arithmetic right shift, '>>'. It rounds towards negative infinity, so the sign is kept.
+impl Shr for Value {
+    type Output = ValueResult;
+
+    fn shr(self, other: Value) -> ValueResult {
+        let (x, n) = shift_operands(self, other, ">>")?;
+        Ok(Value::Int(x >> n))
+    }
+}
+
+impl Value {
+    // Logical right shift, '>>>'. It shifts zeros into the top of the word, so it
+    //   needs a word size to make sense of negative numbers.
+    pub fn logical_shr(self, other: Value) -> ValueResult {
+        let (x, n) = shift_operands(self, other, ">>>")?;
+
+        match word::word() {
+            Some(w) => Ok(Value::Int(w.pattern(&x) >> n)),
+            None if x.is_negative() => {
+                Err("'>>>' on a negative number needs a word size, set one with :word".to_owned())
+            }
+            None => Ok(Value::Int(x >> n)),
+        }
+    }
+
+    // Bitwise not, '~'. This is -x - 1, which flips every bit of a two's complement number.
+    pub fn bit_not(self) -> ValueResult {
+        match self {
+            Value::Int(x) => Ok(Value::Int(!x)),
+            v => Err(format!("Cannot apply ~ to {}", v.type_name())),
+        }
+    }
+}
+
+// The largest shift count outside programmer mode. Shifting further left would
+//   build a number too big to work with.
+const MAX_SHIFT: usize = 1 << 20;
+
+// Checks the operands of a shift and returns the number and the shift count.
+//   In programmer mode, a count past the word size is cut down to one bit past
+//   it, which gives the same result without building a huge number first.
+fn shift_operands(l: Value, r: Value, op: &str) -> Result<(BigInt, usize), String> {
+    match (l, r) {
+        (Value::Int(x), Value::Int(n)) => {
+            if n.is_negative() {
+                return Err(format!("Negative shift count {}", n));
+            }
+
+            let n = match word::word() {
+                Some(w) if n > BigInt::from(w.bits) => w.bits as usize + 1,
+                _ => n
+                    .to_usize()
+                    .filter(|&n| n <= MAX_SHIFT)
+                    .ok_or_else(|| format!("Shift count {} is too large", n))?,
+            };
+
+            Ok((x, n))
+        }
+        (l, r) => Err(format!("Cannot apply {} to {} and {}", op, l.type_name(), r.type_name())),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+// Writes a list of values with their reprs, separated by commas.
+fn write_all(f: &mut fmt::Formatter, items: &[Value], seen: &mut Vec<*const ()>) -> fmt::Result {
+    for (i, v) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        v.write_repr(f, seen)?;
+    }
+    Ok(())
+}
+
+impl Value {
+    // Writes the value, with 'seen' holding the arrays and maps being written around
+    //   it. One met again inside itself is written as '[...]' or '{...}'.
+    fn write(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Rational(x) => write!(f, "{}", x),
+            // The debug format keeps the '.0' on whole numbers, so floats never look like integers.
+            Value::Float(x) => write!(f, "{:?}", x),
+            // Trailing zeros left over from rounding are dropped, but like floats,
+            //   whole decimals keep a '.0'.
+            Value::Decimal(x) => {
+                let s = x.normalized().to_string();
+
+                if s.contains(['.', 'e', 'E']) {
+                    write!(f, "{}", s)
+                } else {
+                    write!(f, "{}.0", s)
+                }
+            }
+            // Complex numbers are written the way they are typed, as in '1+2j' or '-0.5j'.
+            Value::Complex(z) => if z.re == 0f64 {
+                write!(f, "{}j", complex_part(z.im))
+            } else if z.im.is_sign_negative() {
+                write!(f, "{}-{}j", complex_part(z.re), complex_part(-z.im))
+            } else {
+                write!(f, "{}+{}j", complex_part(z.re), complex_part(z.im))
+            },
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Str(x) => write!(f, "{}", x),
+            Value::Array(a) => {
+                let ptr = Rc::as_ptr(a) as *const ();
+
+                if seen.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                seen.push(ptr);
+                write!(f, "[")?;
+                write_all(f, &a.borrow(), seen)?;
+                seen.pop();
+                write!(f, "]")
+            }
+            Value::Map(m) => {
+                let ptr = Rc::as_ptr(m) as *const ();
+
+                if seen.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                seen.push(ptr);
+                write!(f, "{{")?;
+                for (i, e) in m.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    e.0.write_repr(f, seen)?;
+                    write!(f, ": ")?;
+                    e.1.write_repr(f, seen)?;
+                }
+                seen.pop();
+                write!(f, "}}")
+            }
+            // A tuple of one keeps its comma, so it reads back as a tuple.
+            Value::Tuple(t) => {
+                write!(f, "(")?;
+                write_all(f, t, seen)?;
+                if t.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::Function(c) => write!(f, "<fn {}>", c.name()),
+            Value::None => write!(f, "none"),
+        }
+    }
+
+    // Writes the value the way 'repr' does.
+    fn write_repr(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", s),
+            v => v.write(f, seen),
+        }
+    }
+}
+
+// The type of the part of a value that stops it being a map key, if any. Arrays and maps
+//   can change after being added, which would lose track of them, so they cannot be keys.
+fn bad_key(key: &Value) -> Option<&'static str> {
+    match key {
+        Value::Array(_) | Value::Map(_) | Value::None => Some(key.type_name()),
+        Value::Tuple(t) => t.iter().filter_map(bad_key).next(),
+        _ => None,
+    }
+}
+
+// Formats one part of a complex number. Whole parts drop the '.0', since the 'j'
+//   already marks the number as complex, but tiny parts keep the exponent form.
+fn complex_part(x: f64) -> String {
+    let s = format!("{:?}", x);
+
+    if s.ends_with(".0") {
+        s[..s.len() - 2].to_owned()
+    } else {
+        s
+    }
+}
+
+// Writes an integer in base 2, 8, 10 or 16, with the prefix that reads it back in,
+//   as in '0xff' or '-0b101'.
+//
+// In programmer mode, hex, octal and binary show the two's complement bit pattern
+//   instead of a minus sign, and hex and binary are padded to the full word,
+//   so -1 as an i8 is '0xff' and 5 as a u8 is '0b00000101'.
+pub fn int_to_radix(x: &BigInt, radix: u32) -> String {
+    let prefix = match radix {
+        16 => "0x",
+        8 => "0o",
+        2 => "0b",
+        _ => "",
+    };
+
+    if let (Some(w), true) = (word::word(), radix != 10) {
+        let width = match radix {
+            16 => w.bits as usize / 4,
+            2 => w.bits as usize,
+            _ => 0,
+        };
+
+        return format!("{}{:0>width$}", prefix, w.pattern(x).to_str_radix(radix), width = width);
+    }
+
+    format!(
+        "{}{}{}",
+        if x.is_negative() { "-" } else { "" },
+        prefix,
+        x.abs().to_str_radix(radix)
+    )
+}
+
+// The most items an array, or bytes a string, can be made with at once. Anything
+//   bigger is an error rather than a slow run out of memory.
+pub const MAX_LENGTH: usize = 100_000_000;
+
+// How many times to repeat a sequence of the given length. Negative counts give an
+//   empty sequence, and counts that would make it longer than MAX_LENGTH are errors.
+fn repeat_count(n: &BigInt, len: usize) -> Result<usize, String> {
+    if n.is_negative() {
+        return Ok(0);
+    }
+
+    n.to_usize()
+        .filter(|&n| n.checked_mul(len).is_some_and(|total| total <= MAX_LENGTH))
+        .ok_or_else(|| format!("Cannot repeat {} times, it would be too long", n))
+}
+
+// An index as an integer, which is all sequences can be indexed with.
+fn index_int(i: &Value) -> Result<i64, String> {
+    match i {
+        Value::Int(x) => x
+            .to_i64()
+            .ok_or_else(|| format!("Index {} is out of range", x)),
+        v => Err(format!("Indexes must be integers, not {}", v.type_name())),
+    }
+}
+
+// Turns an index into a sequence of the given length into a position, counting
+//   back from the end for negative indexes.
+fn seq_index(len: usize, i: &Value, what: &str) -> Result<usize, String> {
+    let n = index_int(i)?;
+    let p = if n < 0 { n + len as i64 } else { n };
+
+    if p < 0 || p >= len as i64 {
+        Err(format!("{} index {} is out of range for length {}", what, n, len))
+    } else {
+        Ok(p as usize)
+    }
+}
+
+// The positions a slice picks out of a sequence of the given length, in order.
+fn slice_indices(len: usize, start: Option<&Value>, end: Option<&Value>, step: Option<&Value>) -> Result<Vec<usize>, String> {
+    let len = len as i64;
+    let step = match step {
+        Some(s) => index_int(s)?,
+        None => 1,
+    };
+
+    if step == 0 {
+        return Err("Slice step cannot be zero".to_owned());
+    }
+
+    // Bounds are cut down to fit. Going backwards, -1 stands for 'before the start'.
+    let bound = |b: Option<&Value>, default: i64| -> Result<i64, String> {
+        match b {
+            None => Ok(default),
+            Some(v) => {
+                let n = index_int(v)?;
+                let n = if n < 0 { n + len } else { n };
+
+                Ok(if step > 0 {
+                    n.max(0).min(len)
+                } else {
+                    n.max(-1).min(len - 1)
+                })
+            }
+        }
+    };
+
+    let (mut i, end) = if step > 0 {
+        (bound(start, 0)?, bound(end, len)?)
+    } else {
+        (bound(start, len - 1)?, bound(end, -1)?)
+    };
+
+    let mut picked = Vec::new();
+
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        picked.push(i as usize);
+
+        // A huge step can overflow, but it would have gone past the end anyway.
+        i = match i.checked_add(step) {
+            Some(i) => i,
+            None => break,
+        };
+    }
+
+    Ok(picked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bigdecimal::BigDecimal;
+    use std::str::FromStr;
+
+    fn float_literal(text: &str) -> Value {
+        Value::from_token(Token::Number(BigDecimal::from_str(text).unwrap()))
+    }
+
+    fn ints(xs: &[i64]) -> Value {
+        Value::array(xs.iter().map(|&x| Value::Int(BigInt::from(x))).collect())
+    }
+
+    fn int(x: i64) -> Value {
+        Value::Int(BigInt::from(x))
+    }
+
+    fn ratio(n: i64, d: i64) -> Value {
+        Value::from_rational(BigRational::new(BigInt::from(n), BigInt::from(d)))
+    }
+
+    #[test]
+    fn floor_division_and_remainder_work_like_python() {
+        let cases = [(7, 2, 3, 1), (-7, 2, -4, 1), (7, -2, -4, -1), (-7, -2, 3, -1), (6, 3, 2, 0)];
+
+        for &(x, y, q, r) in cases.iter() {
+            assert_eq!(int(x).floor_div(int(y)).unwrap(), int(q), "{} // {}", x, y);
+            assert_eq!((int(x) % int(y)).unwrap(), int(r), "{} % {}", x, y);
+        }
+
+        assert_eq!(ratio(-7, 2).floor_div(int(1)).unwrap(), int(-4));
+        assert_eq!((ratio(-7, 2) % int(1)).unwrap(), ratio(1, 2));
+        assert_eq!(Value::Float(-7.5).floor_div(int(2)).unwrap(), Value::Float(-4.));
+        assert_eq!((Value::Float(-7.5) % int(2)).unwrap(), Value::Float(0.5));
+        assert_eq!((Value::Float(7.5) % int(-2)).unwrap(), Value::Float(-0.5));
+
+        assert!(int(1).floor_div(int(0)).is_err());
+        assert!((int(1) % int(0)).is_err());
+        assert!((Value::Float(1.) % Value::Float(0.)).is_err());
+    }
+
+    #[test]
+    fn exact_numbers_stay_exact() {
+        assert_eq!((int(1) / int(3)).unwrap(), ratio(1, 3));
+        assert_eq!((int(6) / int(3)).unwrap(), int(2));
+        assert_eq!((ratio(1, 3) + ratio(1, 6)).unwrap(), ratio(1, 2));
+        assert_eq!((ratio(1, 2) * int(2)).unwrap(), int(1));
+        assert_eq!(int(2).pow(int(-2)).unwrap(), ratio(1, 4));
+        assert_eq!((ratio(1, 2) + Value::Float(0.25)).unwrap(), Value::Float(0.75));
+    }
+
+    #[test]
+    fn bitwise_operators_treat_negatives_as_twos_complement() {
+        assert_eq!((int(-1) & int(0xff)).unwrap(), int(0xff));
+        assert_eq!(int(5).bit_not().unwrap(), int(-6));
+        assert_eq!((int(-8) >> int(1)).unwrap(), int(-4));
+        assert_eq!((int(1) << int(4)).unwrap(), int(16));
+    }
+
+    #[test]
+    fn integers_are_written_in_any_base() {
+        assert_eq!(int_to_radix(&BigInt::from(255), 16), "0xff");
+        assert_eq!(int_to_radix(&BigInt::from(-5), 2), "-0b101");
+        assert_eq!(int_to_radix(&BigInt::from(8), 8), "0o10");
+        assert_eq!(int_to_radix(&BigInt::from(-42), 10), "-42");
+    }
+
+    #[test]
+    fn words_show_their_bit_pattern() {
+        word::set_word(word::Word::from_name("i8"));
+
+        assert_eq!(int_to_radix(&BigInt::from(-1), 16), "0xff");
+        assert_eq!(int_to_radix(&BigInt::from(5), 2), "0b00000101");
+        assert_eq!(int_to_radix(&BigInt::from(-1), 10), "-1");
+
+        word::set_word(None);
+    }
+
+    #[test]
+    fn slices_work_like_python() {
+        let a = ints(&[1, 2, 3, 4, 5]);
+
+        assert_eq!(a.slice(Some(&int(1)), Some(&int(3)), None).unwrap(), ints(&[2, 3]));
+        assert_eq!(a.slice(None, None, Some(&int(-1))).unwrap(), ints(&[5, 4, 3, 2, 1]));
+        assert_eq!(a.slice(Some(&int(-2)), None, None).unwrap(), ints(&[4, 5]));
+        assert_eq!(a.slice(None, None, Some(&int(2))).unwrap(), ints(&[1, 3, 5]));
+        assert_eq!(a.slice(Some(&int(10)), Some(&int(20)), None).unwrap(), ints(&[]));
+        assert!(a.slice(None, None, Some(&int(0))).is_err());
+    }
+
+    #[test]
+    fn huge_slice_steps_do_not_overflow() {
+        let a = ints(&[1, 2]);
+
+        assert_eq!(a.slice(Some(&int(1)), None, Some(&int(i64::MAX))).unwrap(), ints(&[2]));
+        assert_eq!(a.slice(Some(&int(0)), None, Some(&int(i64::MIN + 1))).unwrap(), ints(&[1]));
+        assert_eq!(a.slice(None, None, Some(&int(i64::MIN))).unwrap(), ints(&[2]));
+    }
+
+    #[test]
+    fn float_literals_are_correctly_rounded() {
+        for text in &["0.1", "1e300", "1.7976931348623157e308", "1e-300", "2.5e-3", "123.456"] {
+            match float_literal(text) {
+                Value::Float(x) => assert_eq!(x, text.parse::<f64>().unwrap(), "{}", text),
+                v => panic!("{} gave {:?}", text, v),
+            }
+        }
+    }
+
+    #[test]
+    fn huge_repeats_are_errors() {
+        let s = Value::Str("ab".to_string());
+
+        assert_eq!((s.clone() * int(3)).unwrap(), Value::Str("ababab".to_string()));
+        assert_eq!((s.clone() * int(-1)).unwrap(), Value::Str(String::new()));
+        assert!((s * int(10_000_000_000)).is_err());
+        assert!((ints(&[0]) * int(10_000_000_000_000)).is_err());
+        assert!((int(i64::MAX) * ints(&[0, 0])).is_err());
+        assert_eq!((Value::array(vec![]) * int(10_000_000_000_000)).unwrap(), Value::array(vec![]));
+    }
+
+    // Makes '[1, a]' and adds it to itself, like 'a = [1]; push(a, a)'.
+    fn holding_itself() -> Value {
+        let a = ints(&[1]);
+
+        if let Value::Array(ref items) = a {
+            items.borrow_mut().push(a.clone());
+        }
+        a
+    }
+
+    #[test]
+    fn arrays_holding_themselves_do_not_loop() {
+        let (a, b) = (holding_itself(), holding_itself());
+
+        assert_eq!(a.to_string(), "[1, [...]]");
+        assert_eq!(Value::tuple(vec![a.clone(), a.clone()]).to_string(), "([1, [...]], [1, [...]])");
+        assert!(a == a);
+        assert!(a == b);
+        assert!(a != ints(&[1]));
+        assert!(!a.is_writable());
+        assert!(Value::array(vec![ints(&[1]), ints(&[1])]).is_writable());
+    }
+
+    // Makes '{1: m}', like 'm = {}; m[1] = m'.
+    fn map_holding_itself() -> Value {
+        let m = Value::map(vec![]);
+
+        if let Value::Map(ref entries) = m {
+            entries.borrow_mut().push((int(1), m.clone()));
+        }
+        m
+    }
+
+    #[test]
+    fn maps_holding_themselves_do_not_loop() {
+        let (m, n) = (map_holding_itself(), map_holding_itself());
+
+        assert_eq!(m.to_string(), "{1: {...}}");
+        assert_eq!(Value::array(vec![m.clone()]).to_string(), "[{1: {...}}]");
+        assert!(m == m);
+        assert!(m == n);
+        assert!(m != Value::map(vec![(int(1), int(1))]));
+        assert!(!m.is_writable());
+    }
+}
@@ -0,0 +1,123 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::One;
+
+use std::cell::Cell;
+
+// A fixed size integer type for programmer mode, like u8 or i32. Signed words
+//   use two's complement, so an i8 holds -128 to 127.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Word {
+    pub bits: u32,
+    pub signed: bool,
+}
+
+// What happens to an integer result that does not fit in the word.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    // Keep the low bits, as fixed size integers do in hardware.
+    Wrap,
+    // Raise an error.
+    Check,
+}
+
+// The programmer mode settings, set with the ':word' and ':overflow' REPL commands.
+//   With no word set, integers are unbounded.
+thread_local! {
+    static SETTINGS: Cell<(Option<Word>, Overflow)> = const { Cell::new((None, Overflow::Wrap)) };
+}
+
+pub fn word() -> Option<Word> {
+    SETTINGS.with(|s| s.get().0)
+}
+
+pub fn set_word(word: Option<Word>) {
+    SETTINGS.with(|s| {
+        let (_, overflow) = s.get();
+        s.set((word, overflow));
+    });
+}
+
+pub fn overflow() -> Overflow {
+    SETTINGS.with(|s| s.get().1)
+}
+
+pub fn set_overflow(overflow: Overflow) {
+    SETTINGS.with(|s| {
+        let (word, _) = s.get();
+        s.set((word, overflow));
+    });
+}
+
+impl Word {
+    // Reads a word size written like a rust integer type, from 'u8' up to 'i128'.
+    pub fn from_name(name: &str) -> Option<Word> {
+        let signed = match name.chars().next() {
+            Some('u') => false,
+            Some('i') => true,
+            _ => return None,
+        };
+
+        match name[1..].parse::<u32>() {
+            Ok(bits) if [8, 16, 32, 64, 128].contains(&bits) => Some(Word {
+                bits,
+                signed,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}{}", if self.signed { "i" } else { "u" }, self.bits)
+    }
+
+    // 2 ** bits, one more than the largest bit pattern.
+    fn modulus(&self) -> BigInt {
+        BigInt::one() << self.bits as usize
+    }
+
+    pub fn min(&self) -> BigInt {
+        if self.signed {
+            -(BigInt::one() << (self.bits - 1) as usize)
+        } else {
+            BigInt::from(0)
+        }
+    }
+
+    pub fn max(&self) -> BigInt {
+        if self.signed {
+            (BigInt::one() << (self.bits - 1) as usize) - 1
+        } else {
+            self.modulus() - 1
+        }
+    }
+
+    // The bit pattern of a number as an unsigned number. A negative number gives
+    //   its two's complement, so -1 is all ones.
+    pub fn pattern(&self, x: &BigInt) -> BigInt {
+        x.mod_floor(&self.modulus())
+    }
+
+    // Keeps the low bits of a number, reading them back as signed if the word is.
+    pub fn wrap(&self, x: &BigInt) -> BigInt {
+        let p = self.pattern(x);
+
+        if self.signed && p > self.max() {
+            p - self.modulus()
+        } else {
+            p
+        }
+    }
+
+    // Brings an integer result into the word, following the overflow setting.
+    pub fn fit(&self, x: BigInt) -> Result<BigInt, String> {
+        if x >= self.min() && x <= self.max() {
+            return Ok(x);
+        }
+
+        match overflow() {
+            Overflow::Wrap => Ok(self.wrap(&x)),
+            Overflow::Check => Err(format!("Overflow: {} does not fit in {}", x, self.name())),
+        }
+    }
+}